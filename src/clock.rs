@@ -0,0 +1,51 @@
+use chrono::{DateTime, Local};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Clock(Arc<dyn Fn() -> DateTime<Local> + Send + Sync>);
+
+impl Clock {
+    pub fn new(now: impl Fn() -> DateTime<Local> + Send + Sync + 'static) -> Clock {
+        Clock(Arc::new(now))
+    }
+
+    pub fn system() -> Clock {
+        Clock::new(Local::now)
+    }
+
+    pub fn now(&self) -> DateTime<Local> {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::system()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::sync::Mutex;
+
+    #[test]
+    fn system_clock_tracks_local_time() {
+        let before = Local::now();
+        let now = Clock::system().now();
+        assert!(now >= before);
+    }
+
+    #[test]
+    fn custom_clock_reads_shared_time() {
+        let start = Local.with_ymd_and_hms(2024, 1, 2, 9, 30, 0).unwrap();
+        let time = Arc::new(Mutex::new(start));
+        let shared = Arc::clone(&time);
+        let clock = Clock::new(move || *shared.lock().unwrap());
+
+        assert_eq!(clock.now(), start);
+        *time.lock().unwrap() += Duration::seconds(5);
+        assert_eq!(clock.now(), start + Duration::seconds(5));
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Eq, PartialEq, Clone, thiserror::Error)]
 pub enum OrderError {
     #[error("Cannot fill order for more than the available quantity")]
     FillOverflow,
@@ -12,4 +12,64 @@ pub enum OrderError {
     CantFullyFill,
     #[error("No liquidity to fill order")]
     NoLiquidity,
+    #[error("Limit price collides with a reserved market order price")]
+    ReservedPrice,
+    #[error("Participant is disconnected")]
+    ParticipantDisconnected,
+    #[error("Bracket take-profit and stop-loss are on the wrong sides")]
+    InvalidBracket,
+    #[error("Order has already been fully filled")]
+    AlreadyFilled,
+    #[error("Order would cross the opposing side")]
+    WouldCross,
+    #[error("Order would lock the market at the opposing touch")]
+    WouldLock,
+    #[error("Order quantity exceeds the maximum order size")]
+    OrderTooLarge,
+    #[error("Book is closed to new orders")]
+    MarketClosed,
+    #[error("Kill switch is engaged")]
+    KillSwitchEngaged,
+    #[error("Quote spread is narrower than the minimum")]
+    SpreadTooTight,
+    #[error("Order has reached the maximum number of modifications")]
+    TooManyModifications,
+    #[error("Order has not rested for the minimum time")]
+    MinRestingTimeNotMet,
+    #[error("Limit price is outside the price band")]
+    OutsidePriceBand,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShutdownError {
+    #[error("Prune thread did not exit before the timeout")]
+    Timeout,
+    #[error("Prune thread panicked")]
+    ThreadPanicked,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Failed to read input")]
+    Io(#[from] std::io::Error),
+    #[error("Line {line}: expected id,type,side,price,quantity")]
+    MalformedRow { line: usize },
+    #[error("Line {line}: unknown order type {order_type:?}")]
+    UnknownOrderType { line: usize, order_type: String },
+    #[error("Line {line}: unknown side {side:?}")]
+    UnknownSide { line: usize, side: String },
+    #[error("Line {line}: price and quantity must be whole numbers")]
+    InvalidNumber { line: usize },
+}
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum ParsePriceError {
+    #[error("Price string is empty")]
+    Empty,
+    #[error("Price contains an invalid character")]
+    InvalidDigit,
+    #[error("Price has more fractional digits than the configured scale")]
+    TooManyDecimals,
+    #[error("Price is too large to represent")]
+    Overflow,
 }
@@ -0,0 +1,50 @@
+use crate::{OrderError, OrderId, Price, Quantity, Side};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum BookEvent {
+    OrderAdded {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    },
+    OrderCancelled {
+        order_id: OrderId,
+    },
+    OrderRejected {
+        order_id: OrderId,
+        reason: OrderError,
+    },
+    TouchImproved {
+        side: Side,
+        old: Option<Price>,
+        new: Price,
+    },
+    SideEmptied {
+        side: Side,
+    },
+    OrderPartiallyFilled {
+        order_id: OrderId,
+        filled: Quantity,
+        remaining: Quantity,
+    },
+    OrderFilled {
+        order_id: OrderId,
+        filled: Quantity,
+    },
+}
+
+impl BookEvent {
+    // Book-level events such as touch changes aren't tied to a single order
+    pub fn order_id(&self) -> Option<&OrderId> {
+        match self {
+            BookEvent::OrderAdded { order_id, .. } => Some(order_id),
+            BookEvent::OrderCancelled { order_id } => Some(order_id),
+            BookEvent::OrderRejected { order_id, .. } => Some(order_id),
+            BookEvent::TouchImproved { .. } => None,
+            BookEvent::SideEmptied { .. } => None,
+            BookEvent::OrderPartiallyFilled { order_id, .. } => Some(order_id),
+            BookEvent::OrderFilled { order_id, .. } => Some(order_id),
+        }
+    }
+}
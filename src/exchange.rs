@@ -0,0 +1,130 @@
+use crate::{Orderbook, Symbol, Trade, TradePrint};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Exchange {
+    books: HashMap<Symbol, Orderbook>,
+}
+
+impl Exchange {
+    pub fn new() -> Exchange {
+        Exchange::default()
+    }
+
+    // Returns the existing book if the symbol is already listed
+    pub fn add_symbol(&mut self, symbol: Symbol) -> &mut Orderbook {
+        self.books.entry(symbol).or_default()
+    }
+
+    pub fn book(&self, symbol: &Symbol) -> Option<&Orderbook> {
+        self.books.get(symbol)
+    }
+
+    pub fn book_mut(&mut self, symbol: &Symbol) -> Option<&mut Orderbook> {
+        self.books.get_mut(symbol)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.books.keys()
+    }
+
+    pub fn prints(&self, symbol: &Symbol) -> Vec<TradePrint> {
+        self.books.get(symbol).map_or_else(Vec::new, |book| {
+            book.trades()
+                .iter()
+                .map(|trade| trade.to_print().with_symbol(symbol.clone()))
+                .collect()
+        })
+    }
+
+    pub fn match_all(&mut self) -> HashMap<Symbol, Vec<Trade>> {
+        self.books
+            .iter_mut()
+            .map(|(symbol, book)| {
+                let last_seen = book.trades().last().map_or(0, |trade| trade.sequence);
+                book.match_orders();
+                let trades = book.trades().since(last_seen).cloned().collect();
+                (symbol.clone(), trades)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Order, OrderId, OrderType, Price, Quantity, Side};
+
+    fn symbol(s: &str) -> Symbol {
+        Symbol::new(s.to_string())
+    }
+
+    fn limit(id: &str, side: Side, p: u64, q: u64) -> Order {
+        Order::new(
+            OrderId::new(id.to_string()),
+            OrderType::GoodTillCancelled,
+            side,
+            Price::new(p),
+            Quantity(q),
+        )
+    }
+
+    #[test]
+    fn add_symbol_is_idempotent() {
+        let mut exchange = Exchange::new();
+        exchange
+            .add_symbol(symbol("AAA"))
+            .add_order(limit("1", Side::Buy, 100, 10))
+            .unwrap();
+        exchange.add_symbol(symbol("AAA"));
+        assert_eq!(exchange.book(&symbol("AAA")).unwrap().order_count(), 1);
+        assert_eq!(exchange.symbols().count(), 1);
+    }
+
+    #[test]
+    fn match_all_keeps_books_independent() {
+        let mut exchange = Exchange::new();
+        let aaa = exchange.add_symbol(symbol("AAA"));
+        aaa.add_order(limit("1", Side::Buy, 100, 10)).unwrap();
+        aaa.add_order(limit("2", Side::Sell, 100, 4)).unwrap();
+        let bbb = exchange.add_symbol(symbol("BBB"));
+        bbb.add_order(limit("1", Side::Buy, 50, 3)).unwrap();
+        bbb.add_order(limit("2", Side::Sell, 49, 3)).unwrap();
+        bbb.add_order(limit("3", Side::Sell, 50, 2)).unwrap();
+
+        let trades = exchange.match_all();
+
+        assert_eq!(trades[&symbol("AAA")].len(), 1);
+        assert_eq!(trades[&symbol("AAA")][0].quantity(), Quantity(4));
+        assert_eq!(trades[&symbol("BBB")].len(), 1);
+        assert_eq!(trades[&symbol("BBB")][0].price(), Price::new(50));
+        assert_eq!(exchange.book(&symbol("BBB")).unwrap().order_count(), 1);
+    }
+
+    #[test]
+    fn match_all_reports_only_new_trades() {
+        let mut exchange = Exchange::new();
+        let book = exchange.add_symbol(symbol("AAA"));
+        book.add_order(limit("1", Side::Buy, 100, 1)).unwrap();
+        book.add_order(limit("2", Side::Sell, 100, 1)).unwrap();
+        exchange.match_all();
+
+        let trades = exchange.match_all();
+        assert!(trades[&symbol("AAA")].is_empty());
+    }
+    #[test]
+    fn prints_carry_the_book_symbol() {
+        let mut exchange = Exchange::new();
+        let book = exchange.add_symbol(symbol("AAA"));
+        book.add_order(limit("1", Side::Sell, 100, 5)).unwrap();
+        book.add_order(limit("2", Side::Buy, 100, 3)).unwrap();
+        exchange.match_all();
+
+        let prints = exchange.prints(&symbol("AAA"));
+        assert_eq!(prints.len(), 1);
+        assert_eq!(prints[0].symbol, Some(symbol("AAA")));
+        assert_eq!(prints[0].quantity, Quantity(3));
+        assert_eq!(prints[0].aggressor, Side::Buy);
+        assert!(exchange.prints(&symbol("ZZZ")).is_empty());
+    }
+}
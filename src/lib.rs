@@ -1,10 +1,17 @@
+pub mod clock;
 pub mod error;
+pub mod event;
+pub mod exchange;
 pub mod order;
 pub mod orderbook;
+mod rng;
 pub mod trade;
 pub mod types;
 
+pub use clock::*;
 pub use error::*;
+pub use event::*;
+pub use exchange::*;
 pub use order::*;
 pub use orderbook::*;
 pub use trade::*;
@@ -1,4 +1,5 @@
 use crate::{OrderError, OrderId, OrderType, Price, Quantity, Side};
+use chrono::{DateTime, Local};
 use std::collections::VecDeque;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -9,11 +10,33 @@ pub struct Order {
     pub price: Price,
     pub initial_quantity: Quantity,
     pub remaining_quantity: Quantity,
+    pub timestamp: DateTime<Local>,
+    pub sequence: u64,
+    pub participant_id: Option<u64>,
+    pub expires_at: Option<DateTime<Local>>,
+    pub reduce_only: bool,
+    pub iceberg: Option<Iceberg>,
+    pub priority_time: Option<DateTime<Local>>,
+    pub post_only: bool,
+}
+
+// Only the display slice rests visibly; the book refills it from the hidden reserve
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Iceberg {
+    pub display_min: Quantity,
+    pub display_max: Quantity,
+    pub hidden: Quantity,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub struct Orders(VecDeque<Order>);
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct StopOrder {
+    pub order: Order,
+    pub stop_price: Price,
+}
+
 impl Order {
     pub fn new(
         order_id: OrderId,
@@ -23,7 +46,7 @@ impl Order {
         initial_quantity: Quantity,
     ) -> Order {
         // Market orders use extreme prices to ensure they match
-        let effective_price = if order_type == OrderType::Market {
+        let effective_price = if order_type.is_market() {
             match side {
                 Side::Buy => Price::max(),  // willing to pay anything
                 Side::Sell => Price::min(), // willing to sell at any price
@@ -39,11 +62,68 @@ impl Order {
             price: effective_price,
             initial_quantity,
             remaining_quantity: initial_quantity,
+            timestamp: Local::now(),
+            sequence: 0,
+            participant_id: None,
+            expires_at: None,
+            reduce_only: false,
+            iceberg: None,
+            priority_time: None,
+            post_only: false,
         }
     }
 
+    pub fn with_participant(mut self, participant_id: u64) -> Order {
+        self.participant_id = Some(participant_id);
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: DateTime<Local>) -> Order {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn with_reduce_only(mut self) -> Order {
+        self.reduce_only = true;
+        self
+    }
+
+    // Replayed flow can carry its recorded queue time instead of the book's arrival time
+    pub fn with_priority_time(mut self, priority_time: DateTime<Local>) -> Order {
+        self.priority_time = Some(priority_time);
+        self
+    }
+
+    pub fn with_post_only(mut self) -> Order {
+        self.post_only = true;
+        self
+    }
+
+    pub fn with_iceberg(mut self, display_min: Quantity, display_max: Quantity) -> Order {
+        self.iceberg = Some(Iceberg {
+            display_min,
+            display_max,
+            hidden: Quantity(0),
+        });
+        self
+    }
+
+    pub fn hidden_quantity(&self) -> Quantity {
+        self.iceberg
+            .map(|iceberg| iceberg.hidden)
+            .unwrap_or(Quantity(0))
+    }
+
+    pub fn leaves_quantity(&self) -> Quantity {
+        self.remaining_quantity + self.hidden_quantity()
+    }
+
+    pub fn queue_time(&self) -> DateTime<Local> {
+        self.priority_time.unwrap_or(self.timestamp)
+    }
+
     pub fn filled_quantity(&self) -> Quantity {
-        self.initial_quantity - self.remaining_quantity
+        self.initial_quantity - self.leaves_quantity()
     }
 
     pub fn fill(&mut self, quantity: Quantity) -> Result<(), OrderError> {
@@ -59,6 +139,15 @@ impl Order {
     }
 }
 
+impl StopOrder {
+    pub fn is_triggered(&self, last_price: Price) -> bool {
+        match self.order.side {
+            Side::Buy => last_price >= self.stop_price,
+            Side::Sell => last_price <= self.stop_price,
+        }
+    }
+}
+
 impl Orders {
     pub fn new() -> Self {
         Orders(VecDeque::new())
@@ -80,10 +169,19 @@ impl Orders {
         self.0.front()
     }
 
+    pub fn back(&self) -> Option<&Order> {
+        self.0.back()
+    }
+
     pub fn front_mut(&mut self) -> Option<&mut Order> {
         self.0.front_mut()
     }
 
+    pub fn remove(&mut self, order_id: OrderId) -> Option<Order> {
+        let pos = self.0.iter().position(|order| order.order_id == order_id)?;
+        self.0.remove(pos)
+    }
+
     pub fn delete(&mut self, order_id: OrderId) {
         if let Some(pos) = self.0.iter().position(|order| order.order_id == order_id) {
             self.0.remove(pos);
@@ -98,6 +196,18 @@ impl Orders {
         self.0.push_back(order);
     }
 
+    pub fn push_front(&mut self, order: Order) {
+        self.0.push_front(order);
+    }
+
+    pub fn position(&self, order_id: OrderId) -> Option<usize> {
+        self.0.iter().position(|order| order.order_id == order_id)
+    }
+
+    pub fn insert(&mut self, index: usize, order: Order) {
+        self.0.insert(index.min(self.0.len()), order);
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &Order> {
         self.0.iter()
     }
@@ -105,6 +215,14 @@ impl Orders {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit();
+    }
 }
 
 #[cfg(test)]
@@ -195,6 +313,39 @@ mod tests {
             assert!(order.is_filled());
         }
 
+        #[test]
+        fn sell_stop_triggers_at_or_below_stop_price() {
+            let stop = StopOrder {
+                order: Order::new(
+                    order_id("1"),
+                    OrderType::Market,
+                    Side::Sell,
+                    price(0),
+                    qty(10),
+                ),
+                stop_price: price(95),
+            };
+            assert!(!stop.is_triggered(price(96)));
+            assert!(stop.is_triggered(price(95)));
+            assert!(stop.is_triggered(price(90)));
+        }
+
+        #[test]
+        fn buy_stop_triggers_at_or_above_stop_price() {
+            let stop = StopOrder {
+                order: Order::new(
+                    order_id("1"),
+                    OrderType::Market,
+                    Side::Buy,
+                    price(0),
+                    qty(10),
+                ),
+                stop_price: price(105),
+            };
+            assert!(!stop.is_triggered(price(104)));
+            assert!(stop.is_triggered(price(105)));
+        }
+
         #[test]
         fn fill_zero_quantity() {
             let mut order = buy_order("1", 100, 50);
@@ -1,15 +1,25 @@
+use crate::rng::XorShift64;
 use crate::{
-    Order, OrderError, OrderId, OrderType, Orders, Price, Quantity, Side, Trade, TradeInfo, Trades,
+    BookEvent, BookPhase, Clock, FeeSchedule, ImportError, LevelPriority, MarketOrderPolicy,
+    MarketState, MidpriceRounding, Order, OrderError, OrderId, OrderStatus, OrderType, Orders,
+    PostOnlyPolicy, Price, PricePriority, Quantity, SessionPnl, ShutdownError, Side, StopOrder,
+    Trade, TradeInfo, TradePricePolicy, Trades,
 };
-use chrono::{Duration, Local, NaiveTime, Timelike};
+use chrono::{DateTime, Duration, Local, NaiveTime};
 use std::cmp::Reverse;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
 use std::ops::Index;
 use std::sync::{
-    Arc, Condvar, Mutex,
+    Arc, Condvar, Mutex, TryLockError, Weak,
     atomic::{AtomicBool, Ordering},
 };
 use std::thread::JoinHandle;
+use std::time::Instant;
+
+const DEFAULT_RNG_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+const DROP_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+const PRUNE_BUSY_RETRY: std::time::Duration = std::time::Duration::from_millis(100);
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub struct LevelInfo {
@@ -26,15 +36,117 @@ pub struct OrderBookLevels {
     asks: LevelInfos,
 }
 
-#[derive(Default)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct BookStats {
+    pub total_trades: usize,
+    pub total_volume: Quantity,
+    pub total_notional: u64,
+    pub vwap: Option<Price>,
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    pub last: Option<Price>,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub open_orders: usize,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct OrderSnapshot {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub initial_quantity: Quantity,
+    pub filled_quantity: Quantity,
+    pub remaining_quantity: Quantity,
+}
+
+// Orders are held in queue order per level so a restore keeps time priority
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct BookSnapshot {
+    pub orders: Vec<Order>,
+    pub dormant_pegs: Vec<Order>,
+    pub next_sequence: u64,
+    pub next_auto_id: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Bracket {
+    entry_id: OrderId,
+    side: Side,
+    quantity: Quantity,
+    participant_id: Option<u64>,
+    take_profit: Price,
+    stop_loss: Price,
+}
+
+#[derive(Clone)]
 pub struct Orderbook {
     bids: BTreeMap<Reverse<Price>, Orders>,
     asks: BTreeMap<Price, Orders>,
     orders: Orders,
     trades: Trades,
+    events: Vec<BookEvent>,
+    clock: Clock,
+    trade_price_policy: TradePricePolicy,
+    level_priority: LevelPriority,
+    price_priority: PricePriority,
+    tick_size: Price,
+    max_order_quantity: Option<Quantity>,
+    min_quote_spread: Option<Price>,
+    price_band: Option<Price>,
+    band_reference: Option<Price>,
+    next_sequence: u64,
+    disconnected: HashSet<u64>,
+    market_close: NaiveTime,
+    brackets: Vec<Bracket>,
+    filled_brackets: Vec<Bracket>,
+    stops: Vec<StopOrder>,
+    rejected_stops: HashSet<OrderId>,
+    dormant_pegs: Vec<Order>,
+    pegs: Vec<OrderId>,
+    oco: HashMap<OrderId, OrderId>,
+    statuses: HashMap<OrderId, OrderStatus>,
+    modifications: HashMap<OrderId, u32>,
+    max_modifications: Option<u32>,
+    min_resting_time: Option<Duration>,
+    positions: HashMap<u64, i64>,
+    total_filled: u64,
+    rng: XorShift64,
+    next_auto_id: u64,
+    auto_match: bool,
+    market_fallback_to_last: bool,
+    market_order_policy: MarketOrderPolicy,
+    midprice_rounding: MidpriceRounding,
+    fee_schedule: FeeSchedule,
+    post_only_policy: PostOnlyPolicy,
+    kill_switch: bool,
+    phase: BookPhase,
+    auction_price: Option<Price>,
+    touch: (Option<Price>, Option<Price>),
+    prune: PruneThread,
+}
+
+// A cloned book gets its own idle prune thread state rather than sharing the original's
+struct PruneThread {
     shutdown: Arc<AtomicBool>,
     shutdown_cv: Arc<(Mutex<()>, Condvar)>,
-    prune_handle: Option<JoinHandle<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PruneThread {
+    fn new() -> PruneThread {
+        PruneThread {
+            shutdown: Arc::new(AtomicBool::new(false)),
+            shutdown_cv: Arc::new((Mutex::new(()), Condvar::new())),
+            handle: None,
+        }
+    }
+}
+
+impl Clone for PruneThread {
+    fn clone(&self) -> Self {
+        PruneThread::new()
+    }
 }
 
 impl LevelInfos {
@@ -57,963 +169,7888 @@ impl Index<usize> for LevelInfos {
 
 impl Orderbook {
     pub fn new() -> Orderbook {
-        let shutdown = Arc::new(AtomicBool::new(false));
-        let shutdown_cv = Arc::new((Mutex::new(()), Condvar::new()));
         Orderbook {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             orders: Orders::new(),
             trades: Trades::new(),
-            shutdown,
-            shutdown_cv,
-            prune_handle: None,
+            events: Vec::new(),
+            clock: Clock::system(),
+            trade_price_policy: TradePricePolicy::default(),
+            level_priority: LevelPriority::default(),
+            price_priority: PricePriority::default(),
+            tick_size: Price::new(1),
+            max_order_quantity: None,
+            min_quote_spread: None,
+            price_band: None,
+            band_reference: None,
+            next_sequence: 0,
+            disconnected: HashSet::new(),
+            market_close: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            brackets: Vec::new(),
+            filled_brackets: Vec::new(),
+            stops: Vec::new(),
+            rejected_stops: HashSet::new(),
+            dormant_pegs: Vec::new(),
+            pegs: Vec::new(),
+            oco: HashMap::new(),
+            statuses: HashMap::new(),
+            modifications: HashMap::new(),
+            max_modifications: None,
+            min_resting_time: None,
+            positions: HashMap::new(),
+            total_filled: 0,
+            rng: XorShift64::new(DEFAULT_RNG_SEED),
+            next_auto_id: 0,
+            auto_match: false,
+            market_fallback_to_last: false,
+            market_order_policy: MarketOrderPolicy::default(),
+            midprice_rounding: MidpriceRounding::default(),
+            fee_schedule: FeeSchedule::default(),
+            post_only_policy: PostOnlyPolicy::default(),
+            kill_switch: false,
+            phase: BookPhase::default(),
+            auction_price: None,
+            touch: (None, None),
+            prune: PruneThread::new(),
         }
     }
 
-    pub fn midprice(&self) -> Option<Price> {
-        let best_bid = self.bids.keys().next().map(|Reverse(p)| p)?;
-        let best_ask = self.asks.keys().next()?;
-        Some(Price::new((best_bid.0 + best_ask.0) / 2))
-    }
-
-    pub fn add_order(&mut self, order: Order) -> Result<(), OrderError> {
-        if self.orders.contains(order.clone().order_id) {
-            return Err(OrderError::IdExists);
-        }
-
-        match order.order_type {
-            OrderType::FillAndKill => {
-                if order.order_type == OrderType::FillAndKill
-                    && !self.can_match(order.side, order.price)
-                {
-                    return Err(OrderError::CantMatch);
-                }
-            }
-            OrderType::FillOrKill => {
-                if !self.can_fully_fill(order.side, order.price, order.remaining_quantity) {
-                    return Err(OrderError::CantFullyFill);
-                }
-            }
-            OrderType::Market => {
-                if !self.has_liquidity(order.side) {
-                    return Err(OrderError::NoLiquidity);
-                }
-            }
-            _ => {}
+    // Seeds one GTC order per level; crossed input is rejected rather than matched
+    pub fn from_levels(
+        bids: &[(Price, Quantity)],
+        asks: &[(Price, Quantity)],
+    ) -> Result<Orderbook, OrderError> {
+        let best_bid = bids.iter().map(|&(p, _)| p).max();
+        let best_ask = asks.iter().map(|&(p, _)| p).min();
+        if let (Some(bid), Some(ask)) = (best_bid, best_ask)
+            && bid >= ask
+        {
+            return Err(OrderError::WouldCross);
         }
 
-        match order.side {
-            Side::Buy => {
-                if let Some(orders) = self.bids.get_mut(&Reverse(order.clone().price)) {
-                    orders.push_back(order.clone());
-                    self.orders.push_back(order.clone());
-                } else {
-                    let mut orders = Orders::new();
-                    orders.push_back(order.clone());
-                    self.bids.insert(Reverse(order.price), orders);
-                    self.orders.push_back(order);
-                }
-            }
-            Side::Sell => {
-                if let Some(orders) = self.asks.get_mut(&order.price) {
-                    orders.push_back(order.clone());
-                    self.orders.push_back(order);
-                } else {
-                    let mut orders = Orders::new();
-                    orders.push_back(order.clone());
-                    self.asks.insert(order.price, orders);
-                    self.orders.push_back(order);
-                }
-            }
+        let mut book = Orderbook::new();
+        let levels = bids
+            .iter()
+            .map(|&level| (Side::Buy, level))
+            .chain(asks.iter().map(|&level| (Side::Sell, level)));
+        for (side, (price, quantity)) in levels {
+            let order_id = book.generate_order_id();
+            book.add_order(Order::new(
+                order_id,
+                OrderType::GoodTillCancelled,
+                side,
+                price,
+                quantity,
+            ))?;
         }
-
-        Ok(())
+        Ok(book)
     }
 
-    pub fn modify_order(
-        &mut self,
-        order_id: OrderId,
-        new_quantity: Quantity,
-    ) -> Result<(), OrderError> {
-        let (side, price) = {
-            let order = self
-                .orders
-                .get(order_id.clone())
-                .ok_or(OrderError::OrderNotFound)?;
-            (order.side, order.price)
-        };
-
-        // Update in orders
-        if let Some(o) = self.orders.get_mut(order_id.clone()) {
-            o.remaining_quantity = new_quantity;
+    pub fn snapshot(&self) -> BookSnapshot {
+        let orders = self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flat_map(|orders| orders.iter().cloned())
+            .collect();
+        BookSnapshot {
+            orders,
+            dormant_pegs: self.dormant_pegs.clone(),
+            next_sequence: self.next_sequence,
+            next_auto_id: self.next_auto_id,
         }
+    }
 
-        // Update in bids/asks
-        match side {
-            Side::Buy => {
-                if let Some(orders) = self.bids.get_mut(&Reverse(price))
-                    && let Some(o) = orders.get_mut(order_id.clone())
-                {
-                    o.remaining_quantity = new_quantity;
-                }
-            }
-            Side::Sell => {
-                if let Some(orders) = self.asks.get_mut(&price)
-                    && let Some(o) = orders.get_mut(order_id)
-                {
-                    o.remaining_quantity = new_quantity;
-                }
+    // Counters come back too, so ids and sequences issued after a restore never repeat
+    pub fn restore(snapshot: BookSnapshot) -> Orderbook {
+        let mut book = Orderbook::new();
+        for order in snapshot.orders {
+            book.statuses
+                .insert(order.order_id.clone(), OrderStatus::Open);
+            book.orders.push_back(order.clone());
+            match order.side {
+                Side::Buy => book.bids.entry(Reverse(order.price)).or_default(),
+                Side::Sell => book.asks.entry(order.price).or_default(),
             }
+            .push_back(order);
         }
-
-        Ok(())
+        book.pegs = book
+            .orders
+            .iter()
+            .filter(|order| order.order_type.is_pegged())
+            .map(|order| order.order_id.clone())
+            .collect();
+        for order in &snapshot.dormant_pegs {
+            book.statuses
+                .insert(order.order_id.clone(), OrderStatus::Open);
+        }
+        book.dormant_pegs = snapshot.dormant_pegs;
+        book.next_sequence = snapshot.next_sequence;
+        book.next_auto_id = snapshot.next_auto_id;
+        book.touch = (book.best_price(Side::Buy), book.best_price(Side::Sell));
+        book
     }
 
-    fn can_match(&self, side: Side, price: Price) -> bool {
-        match side {
-            Side::Buy => {
-                if let Some(&ask_price) = self.asks.keys().next() {
-                    ask_price <= price
-                } else {
-                    false
-                }
-            }
-            Side::Sell => {
-                if let Some(&Reverse(bid_price)) = self.bids.keys().next() {
-                    bid_price >= price
-                } else {
-                    false
-                }
-            }
-        }
+    pub fn midprice(&self) -> Option<Price> {
+        let best_bid = self.best_price(Side::Buy)?;
+        let best_ask = self.best_price(Side::Sell)?;
+        Some(self.mid_of(best_bid, best_ask))
     }
 
-    pub fn can_fully_fill(&self, side: Side, price: Price, remaining_quantity: Quantity) -> bool {
-        let available = match side {
-            Side::Buy => self
-                .asks
-                .iter()
-                .filter(|(p, _)| **p <= price)
-                .flat_map(|(_, orders)| orders.iter())
-                .map(|o| o.remaining_quantity.0)
-                .sum::<u64>(),
-            Side::Sell => self
-                .bids
-                .iter()
-                .filter(|(rp, _)| rp.0 >= price)
-                .flat_map(|(_, orders)| orders.iter())
-                .map(|o| o.remaining_quantity.0)
-                .sum::<u64>(),
-        };
-        available >= remaining_quantity.0
+    fn mid_of(&self, best_bid: Price, best_ask: Price) -> Price {
+        let (low, high) = (best_bid.min(best_ask).0, best_bid.max(best_ask).0);
+        let half_spread = (high - low) / 2;
+        let has_half = (high - low) % 2 == 1;
+        let round_up = has_half
+            && match self.midprice_rounding {
+                MidpriceRounding::Down => false,
+                MidpriceRounding::Up => true,
+                MidpriceRounding::Nearest => (low + half_spread) % 2 == 1,
+            };
+        Price::new(low + half_spread + u64::from(round_up))
     }
-    pub fn has_liquidity(&self, side: Side) -> bool {
-        match side {
-            Side::Buy => !self.asks.is_empty(),
-            Side::Sell => !self.bids.is_empty(),
-        }
+
+    pub fn set_midprice_rounding(&mut self, rounding: MidpriceRounding) {
+        self.midprice_rounding = rounding;
     }
 
-    pub fn match_orders(&mut self) {
-        // While we have bids and asks
-        while let (Some(&Reverse(best_bid_price)), Some(&best_ask_price)) =
-            (self.bids.keys().next(), self.asks.keys().next())
-        {
-            if best_bid_price < best_ask_price {
-                break;
-            }
+    pub fn set_fee_schedule(&mut self, schedule: FeeSchedule) {
+        self.fee_schedule = schedule;
+    }
 
-            // Get order info and fill amount
-            let (bid_id, ask_id, to_fill) = {
-                let bid_orders = self.bids.get_mut(&Reverse(best_bid_price)).unwrap();
-                let ask_orders = self.asks.get_mut(&best_ask_price).unwrap();
-                let bid_order = bid_orders.front_mut().unwrap();
-                let ask_order = ask_orders.front_mut().unwrap();
+    pub fn market_state(&self) -> MarketState {
+        match (self.best_price(Side::Buy), self.best_price(Side::Sell)) {
+            (Some(bid), Some(ask)) if bid == ask => MarketState::Locked,
+            (Some(bid), Some(ask)) if self.crosses(bid, ask) => MarketState::Crossed,
+            _ => MarketState::Normal,
+        }
+    }
 
-                let to_fill =
-                    std::cmp::min(bid_order.remaining_quantity, ask_order.remaining_quantity);
-                bid_order.fill(to_fill).ok();
-                ask_order.fill(to_fill).ok();
+    pub fn weighted_mid(&self, depth: usize) -> Option<Price> {
+        if self.bids.is_empty() || self.asks.is_empty() {
+            return None;
+        }
 
+        let (notional, volume) = self
+            .levels_best_first(Side::Buy)
+            .take(depth)
+            .chain(self.levels_best_first(Side::Sell).take(depth))
+            .fold((0u64, 0u64), |(notional, volume), (price, orders)| {
+                let quantity = Self::level_quantity(orders);
                 (
-                    bid_order.order_id.clone(),
-                    ask_order.order_id.clone(),
-                    to_fill,
+                    notional.saturating_add(price.notional(quantity)),
+                    volume.saturating_add(quantity.0),
                 )
-            }; // borrows end here
-
-            // Record trade
-            let trade_price = if best_ask_price == Price::min() {
-                best_bid_price
-            } else {
-                best_ask_price
-            };
-
-            self.trades.push(Trade {
-                bid_trade: TradeInfo::new(bid_id.clone(), trade_price, to_fill),
-                ask_trade: TradeInfo::new(ask_id.clone(), trade_price, to_fill),
             });
 
-            // Remove filled orders and clean up empty levels
-            if let Some(bid_orders) = self.bids.get_mut(&Reverse(best_bid_price)) {
-                if bid_orders.front().map(|o| o.is_filled()).unwrap_or(false) {
-                    bid_orders.pop();
-                    self.orders.delete(bid_id.clone());
-                }
-                if bid_orders.is_empty() {
-                    self.bids.remove(&Reverse(best_bid_price));
-                }
-            }
-
-            if let Some(ask_orders) = self.asks.get_mut(&best_ask_price) {
-                if ask_orders.front().map(|o| o.is_filled()).unwrap_or(false) {
-                    ask_orders.pop();
-                    self.orders.delete(ask_id.clone());
-                }
-                if ask_orders.is_empty() {
-                    self.asks.remove(&best_ask_price);
-                }
-            }
-        }
-        // We need to remove FillAndKills with no other side
-        let bid_fak_ids: Vec<OrderId> = self
-            .bids
-            .first_key_value()
-            .map(|(_, orders)| {
-                orders
-                    .iter()
-                    .filter(|order| {
-                        matches!(order.order_type, OrderType::FillAndKill | OrderType::Market)
-                    })
-                    .map(|order| order.order_id.clone())
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        let ask_fak_ids: Vec<OrderId> = self
-            .asks
-            .first_key_value()
-            .map(|(_, orders)| {
-                orders
-                    .iter()
-                    .filter(|order| {
-                        matches!(order.order_type, OrderType::FillAndKill | OrderType::Market)
-                    })
-                    .map(|order| order.order_id.clone())
-                    .collect()
-            })
-            .unwrap_or_default();
+        (volume > 0).then(|| Price::new(notional / volume))
+    }
 
-        for id in bid_fak_ids.into_iter().chain(ask_fak_ids) {
-            let _ = self.cancel_order(id);
-        }
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
     }
 
-    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<(), OrderError> {
-        let (side, price) = {
-            let order = self
-                .orders
-                .get(order_id.clone())
-                .ok_or(OrderError::OrderNotFound)?;
-            (order.side, order.price)
-        };
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = XorShift64::new(seed);
+    }
 
-        self.orders.delete(order_id.clone());
+    pub fn set_tick_size(&mut self, tick_size: Price) {
+        self.tick_size = tick_size;
+    }
 
-        match side {
-            Side::Buy => {
-                if let Some(bids_at_price) = self.bids.get_mut(&Reverse(price)) {
-                    bids_at_price.delete(order_id);
-                    if bids_at_price.is_empty() {
-                        self.bids.remove(&Reverse(price));
-                    }
-                }
-            }
-            Side::Sell => {
-                if let Some(asks_at_price) = self.asks.get_mut(&price) {
-                    asks_at_price.delete(order_id);
-                    if asks_at_price.is_empty() {
-                        self.asks.remove(&price);
-                    }
-                }
-            }
-        }
-        Ok(())
+    pub fn set_max_order_quantity(&mut self, max_order_quantity: Option<Quantity>) {
+        self.max_order_quantity = max_order_quantity;
     }
 
-    pub fn get_levels(&self) -> OrderBookLevels {
-        let bids = LevelInfos(
-            self.bids
-                .iter()
-                .map(|(Reverse(price), orders)| LevelInfo {
-                    price: *price,
-                    quantity: orders
-                        .iter()
-                        .map(|order| order.remaining_quantity)
-                        .fold(Quantity(0), |acc, q| Quantity(acc.0.saturating_add(q.0))),
-                })
-                .collect(),
-        );
+    pub fn set_min_resting_time(&mut self, min_resting_time: Option<Duration>) {
+        self.min_resting_time = min_resting_time;
+    }
 
-        let asks = LevelInfos(
-            self.asks
-                .iter()
-                .map(|(price, orders)| LevelInfo {
-                    price: *price,
-                    quantity: orders
-                        .iter()
-                        .map(|order| order.remaining_quantity)
-                        .fold(Quantity(0), |acc, q| Quantity(acc.0.saturating_add(q.0))),
-                })
-                .collect(),
-        );
+    pub fn set_max_modifications(&mut self, max_modifications: Option<u32>) {
+        self.max_modifications = max_modifications;
+    }
 
-        OrderBookLevels { bids, asks }
+    // Only enforced by quote(); plain adds may still tighten the spread
+    pub fn set_min_quote_spread(&mut self, min_quote_spread: Option<Price>) {
+        self.min_quote_spread = min_quote_spread;
     }
 
-    pub fn trades(&self) -> &Trades {
-        &self.trades
+    // Limits must sit within band ticks of the reference; either left unset disables the check
+    pub fn set_price_band(&mut self, band: Option<Price>) {
+        self.price_band = band;
     }
 
-    pub fn clear_trades(&mut self) {
-        self.trades.clear();
+    // Resting orders are left alone until enforce_price_bands is called
+    pub fn set_reference_price(&mut self, reference: Price) {
+        self.band_reference = Some(reference);
     }
-    pub fn prune_good_for_day_orders(&mut self) {
-        let gfd_ids: Vec<OrderId> = self
-            .orders
+
+    // Dormant pegs have no price yet; they are checked when they next take one from the book
+    pub fn enforce_price_bands(&mut self) -> usize {
+        let Some((low, high)) = self.price_band_limits() else {
+            return 0;
+        };
+        let dormant: HashSet<OrderId> = self
+            .dormant_pegs
             .iter()
-            .filter(|o| o.order_type == OrderType::GoodForDay)
-            .map(|o| o.order_id.clone())
+            .map(|order| order.order_id.clone())
             .collect();
-
-        for id in gfd_ids {
-            let _ = self.cancel_order(id);
-        }
+        self.cancel_where(|o| {
+            !o.order_type.is_market()
+                && !dormant.contains(&o.order_id)
+                && (o.price < low || o.price > high)
+        })
     }
 
-    #[allow(dead_code)]
-    fn run_prune_thread(
-        orderbook: Arc<Mutex<Self>>,
-        shutdown: Arc<AtomicBool>,
-        shutdown_cv: Arc<(Mutex<()>, Condvar)>,
-    ) {
-        const MARKET_CLOSE_HOUR: u32 = 16;
+    pub fn set_price_priority(&mut self, priority: PricePriority) {
+        self.price_priority = priority;
+    }
 
-        loop {
-            let now = Local::now();
-            let today_close = now
-                .date_naive()
-                .and_time(NaiveTime::from_hms_opt(MARKET_CLOSE_HOUR, 0, 0).unwrap());
+    pub fn set_level_priority(&mut self, priority: LevelPriority) {
+        self.level_priority = priority;
+    }
 
-            let next_close = if now.time().hour() >= MARKET_CLOSE_HOUR {
-                today_close + Duration::days(1)
-            } else {
-                today_close
-            };
+    pub fn set_phase(&mut self, phase: BookPhase) {
+        self.phase = phase;
+    }
 
-            let wait_duration = (next_close - now.naive_local())
-                .to_std()
-                .unwrap_or(std::time::Duration::from_millis(100))
-                + std::time::Duration::from_millis(100);
+    pub fn phase(&self) -> BookPhase {
+        self.phase
+    }
 
-            // Wait until market close or shutdown
-            {
-                let (lock, cvar) = &*shutdown_cv;
-                let guard = lock.lock().unwrap();
-                let result = cvar.wait_timeout(guard, wait_duration).unwrap();
+    pub fn set_auto_match(&mut self, auto_match: bool) {
+        self.auto_match = auto_match;
+    }
 
-                if shutdown.load(Ordering::Acquire) || !result.1.timed_out() {
-                    return;
-                }
-            }
+    // Unlike a halt, nothing new is accepted; cancels still go through so participants can pull out
+    pub fn enable_kill_switch(&mut self) {
+        self.kill_switch = true;
+    }
 
-            // Collect GoodForDay order IDs
-            let order_ids: Vec<OrderId> = {
-                let ob = orderbook.lock().unwrap();
-                ob.orders
-                    .iter()
-                    .filter(|o| o.order_type == OrderType::GoodForDay)
-                    .map(|o| o.order_id.clone())
-                    .collect()
-            };
+    pub fn disable_kill_switch(&mut self) {
+        self.kill_switch = false;
+    }
 
-            // Cancel them
+    pub fn is_kill_switch_engaged(&self) -> bool {
+        self.kill_switch
+    }
 
-            {
-                let mut ob = orderbook.lock().unwrap();
-                for id in order_ids {
-                    let _ = ob.cancel_order(id);
-                }
-            }
-        }
+    pub fn set_market_fallback_to_last(&mut self, fallback: bool) {
+        self.market_fallback_to_last = fallback;
     }
 
-    pub fn shutdown(&mut self) {
-        self.shutdown.store(true, Ordering::Release);
-        let (_, cvar) = &*self.shutdown_cv;
-        cvar.notify_all();
+    pub fn set_market_order_policy(&mut self, policy: MarketOrderPolicy) {
+        self.market_order_policy = policy;
+    }
 
-        if let Some(handle) = self.prune_handle.take() {
-            handle.join().ok();
-        }
+    pub fn set_post_only_policy(&mut self, policy: PostOnlyPolicy) {
+        self.post_only_policy = policy;
     }
-}
 
-impl Drop for Orderbook {
-    fn drop(&mut self) {
-        self.shutdown();
+    pub fn set_market_close(&mut self, market_close: NaiveTime) {
+        self.market_close = market_close;
     }
-}
 
-impl OrderBookLevels {
-    pub fn bids(&self) -> &[LevelInfo] {
-        &self.bids.0
+    pub fn set_trade_price_policy(&mut self, policy: TradePricePolicy) {
+        self.trade_price_policy = policy;
     }
-    pub fn asks(&self) -> &[LevelInfo] {
-        &self.asks.0
+
+    pub fn best_bid_age(&self, now: DateTime<Local>) -> Option<Duration> {
+        let (_, orders) = self.levels_best_first(Side::Buy).next()?;
+        Self::oldest_arrival(orders).map(|arrived| now - arrived)
     }
-}
 
-impl LevelInfo {
-    pub fn price(&self) -> Price {
-        self.price
+    pub fn best_ask_age(&self, now: DateTime<Local>) -> Option<Duration> {
+        let (_, orders) = self.levels_best_first(Side::Sell).next()?;
+        Self::oldest_arrival(orders).map(|arrived| now - arrived)
     }
-    pub fn quantity(&self) -> Quantity {
-        self.quantity
+
+    fn oldest_arrival(orders: &Orders) -> Option<DateTime<Local>> {
+        orders.iter().map(|order| order.timestamp).min()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::Price;
+    pub fn add_order(&mut self, order: Order) -> Result<(), OrderError> {
+        let order_id = order.order_id.clone();
+        self.accept_order(order).inspect_err(|reason| {
+            self.events.push(BookEvent::OrderRejected {
+                order_id,
+                reason: reason.clone(),
+            });
+        })
+    }
 
-    fn price(p: u64) -> Price {
-        Price::new(p)
+    // Runs every insert-time check without touching the book, so a pre-flight matches add_order
+    pub fn validate(&self, order: &Order) -> Result<(), OrderError> {
+        self.validate_with_id(order, false)
     }
 
-    fn qty(q: u64) -> Quantity {
-        Quantity(q)
-    }
+    // A replacement reuses the id of the order it is about to cancel
+    fn validate_with_id(&self, order: &Order, replacing: bool) -> Result<(), OrderError> {
+        if self.kill_switch {
+            return Err(OrderError::KillSwitchEngaged);
+        }
 
-    fn order_id(id: &str) -> OrderId {
-        OrderId::new(id.to_string())
-    }
+        if self.phase == BookPhase::Closed {
+            return Err(OrderError::MarketClosed);
+        }
 
-    fn buy_order(id: String, p: u64, q: u64) -> Order {
-        Order::new(
-            OrderId::new(id),
-            OrderType::GoodTillCancelled,
-            Side::Buy,
-            price(p),
-            qty(q),
-        )
-    }
+        if !replacing && self.id_in_use(&order.order_id) {
+            return Err(OrderError::IdExists);
+        }
 
-    fn sell_order(id: String, p: u64, q: u64) -> Order {
-        Order::new(
-            OrderId::new(id),
-            OrderType::GoodTillCancelled,
-            Side::Sell,
-            price(p),
-            qty(q),
-        )
-    }
+        if let Some(participant_id) = order.participant_id
+            && self.disconnected.contains(&participant_id)
+        {
+            return Err(OrderError::ParticipantDisconnected);
+        }
 
-    fn buy_fak(id: String, p: u64, q: u64) -> Order {
-        Order::new(
-            OrderId::new(id),
-            OrderType::FillAndKill,
-            Side::Buy,
-            price(p),
-            qty(q),
-        )
-    }
+        if self
+            .max_order_quantity
+            .is_some_and(|max| order.initial_quantity > max)
+        {
+            return Err(OrderError::OrderTooLarge);
+        }
 
-    mod orderbook {
-        use super::*;
+        // Price::max()/min() are reserved for market orders
+        if !order.order_type.is_market()
+            && !order.order_type.is_pegged()
+            && (order.price == Price::max() || order.price == Price::min())
+        {
+            return Err(OrderError::ReservedPrice);
+        }
 
-        #[test]
-        fn new_orderbook_is_empty() {
-            let ob = Orderbook::new();
-            let levels = ob.get_levels();
-            assert!(levels.bids.0.is_empty());
-            assert!(levels.asks.0.is_empty());
+        // Pegs are checked once they have a price from the book
+        if !order.order_type.is_market() && !order.order_type.is_pegged() {
+            self.check_price_band(order.price)?;
         }
 
-        #[test]
-        fn add_buy_order() {
-            let mut ob = Orderbook::new();
-            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-            let levels = ob.get_levels();
-            assert_eq!(levels.bids.0.len(), 1);
-            assert_eq!(levels.bids.0[0].price, price(100));
-            assert_eq!(levels.bids.0[0].quantity, qty(50));
+        if order.post_only {
+            self.check_post_only(
+                order.side,
+                order.price,
+                self.best_price(order.side.opposite()),
+            )?;
         }
 
-        #[test]
-        fn add_sell_order() {
-            let mut ob = Orderbook::new();
-            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-            let levels = ob.get_levels();
-            assert_eq!(levels.asks.0.len(), 1);
-            assert_eq!(levels.asks.0[0].price, price(100));
-            assert_eq!(levels.asks.0[0].quantity, qty(50));
+        if self.market_fallback_price(order).is_some() {
+            return Ok(());
         }
 
-        #[test]
-        fn add_duplicate_id_fails() {
-            let mut ob = Orderbook::new();
-            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-            let result = ob.add_order(buy_order("1".to_string(), 110, 60));
-            assert!(matches!(result, Err(OrderError::IdExists)));
+        match order.order_type {
+            OrderType::FillAndKill if !self.can_match(order.side, order.price) => {
+                Err(OrderError::CantMatch)
+            }
+            OrderType::FillOrKill
+                if !self.can_fully_fill(order.side, order.price, order.remaining_quantity) =>
+            {
+                Err(OrderError::CantFullyFill)
+            }
+            OrderType::Market | OrderType::MarketToLimit if !self.has_liquidity(order.side) => {
+                Err(OrderError::NoLiquidity)
+            }
+            OrderType::Market
+                if self.market_order_policy == MarketOrderPolicy::Reject
+                    && !self.can_fill_order(order) =>
+            {
+                Err(OrderError::CantFullyFill)
+            }
+            _ => Ok(()),
         }
+    }
 
-        #[test]
-        fn add_multiple_orders_same_price() {
-            let mut ob = Orderbook::new();
-            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-            ob.add_order(buy_order("2".to_string(), 100, 30)).unwrap();
-            let levels = ob.get_levels();
-            assert_eq!(levels.bids.0.len(), 1);
-            assert_eq!(levels.bids.0[0].quantity, qty(80));
+    // Checked on arrival and on every reprice, against the touch the order would face
+    fn check_post_only(
+        &self,
+        side: Side,
+        price: Price,
+        opposing_touch: Option<Price>,
+    ) -> Result<(), OrderError> {
+        let Some(touch) = opposing_touch else {
+            return Ok(());
+        };
+        let crosses = match side {
+            Side::Buy => self.crosses(price, touch),
+            Side::Sell => self.crosses(touch, price),
+        };
+        if price == touch {
+            if self.post_only_policy == PostOnlyPolicy::RejectLock {
+                return Err(OrderError::WouldLock);
+            }
+        } else if crosses {
+            return Err(OrderError::WouldCross);
         }
+        Ok(())
+    }
 
-        #[test]
-        fn add_multiple_orders_different_prices() {
-            let mut ob = Orderbook::new();
-            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-            ob.add_order(buy_order("2".to_string(), 110, 30)).unwrap();
-            ob.add_order(buy_order("3".to_string(), 90, 20)).unwrap();
-            let levels = ob.get_levels();
-            assert_eq!(levels.bids.0.len(), 3);
-            assert_eq!(levels.bids.0[0].price, price(110));
-            assert_eq!(levels.bids.0[1].price, price(100));
-            assert_eq!(levels.bids.0[2].price, price(90));
+    // With nothing to take, a market order can rest as a limit at the last trade instead
+    fn market_fallback_price(&self, order: &Order) -> Option<Price> {
+        if order.order_type.is_market()
+            && self.market_fallback_to_last
+            && !self.has_liquidity(order.side)
+        {
+            self.last_trade_price()
+        } else {
+            None
         }
+    }
 
-        #[test]
-        fn asks_sorted_low_to_high() {
-            let mut ob = Orderbook::new();
-            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-            ob.add_order(sell_order("2".to_string(), 110, 30)).unwrap();
-            ob.add_order(sell_order("3".to_string(), 90, 20)).unwrap();
-            let levels = ob.get_levels();
-            assert_eq!(levels.asks.0.len(), 3);
-            assert_eq!(levels.asks.0[0].price, price(90));
-            assert_eq!(levels.asks.0[1].price, price(100));
-            assert_eq!(levels.asks.0[2].price, price(110));
+    fn accept_order(&mut self, order: Order) -> Result<(), OrderError> {
+        self.validate(&order)?;
+
+        let mut order = order;
+        if let Some(last) = self.market_fallback_price(&order) {
+            order.order_type = OrderType::GoodTillCancelled;
+            order.price = last;
+        }
+        // Resting at the last fill is exactly what MarketToLimit already does
+        if order.order_type == OrderType::Market
+            && self.market_order_policy == MarketOrderPolicy::RestAsLimitAtLast
+        {
+            order.order_type = OrderType::MarketToLimit;
         }
 
-        #[test]
-        fn can_match_buy_when_ask_exists_at_or_below() {
-            let mut ob = Orderbook::new();
-            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-            assert!(ob.can_match(Side::Buy, price(100)));
-            assert!(ob.can_match(Side::Buy, price(110)));
-            assert!(!ob.can_match(Side::Buy, price(90)));
+        if order.order_type.is_market() {
+            order.price = self.market_price(order.side);
         }
 
-        #[test]
-        fn can_match_sell_when_bid_exists_at_or_above() {
-            let mut ob = Orderbook::new();
-            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-            assert!(ob.can_match(Side::Sell, price(100)));
-            assert!(ob.can_match(Side::Sell, price(90)));
-            assert!(!ob.can_match(Side::Sell, price(110)));
+        // Stamp arrival time and sequence from the book
+        order.timestamp = self.clock.now();
+        self.next_sequence += 1;
+        order.sequence = self.next_sequence;
+
+        // A relative TTL becomes an absolute expiry from the book's arrival time
+        if let OrderType::GoodForDuration(ttl) = order.order_type {
+            let expires_at = order.timestamp + ttl;
+            order.expires_at = Some(order.expires_at.map_or(expires_at, |e| e.min(expires_at)));
         }
 
-        #[test]
-        fn can_match_false_on_empty_book() {
-            let ob = Orderbook::new();
-            assert!(!ob.can_match(Side::Buy, price(100)));
-            assert!(!ob.can_match(Side::Sell, price(100)));
+        self.statuses
+            .insert(order.order_id.clone(), OrderStatus::Open);
+
+        // Icebergs start with everything in reserve and show a first slice
+        if let Some(iceberg) = order.iceberg.as_mut() {
+            iceberg.hidden = order.remaining_quantity;
+            order.remaining_quantity = Quantity(0);
+            self.replenish(&mut order);
         }
 
-        #[test]
-        fn fak_rejected_when_no_match_possible() {
-            let mut ob = Orderbook::new();
-            let result = ob.add_order(buy_fak("1".to_string(), 100, 50));
-            assert!(matches!(result, Err(OrderError::CantMatch)));
+        // Pegs take their price from the book; with nothing to peg to they wait off-book
+        if order.order_type.is_pegged() {
+            match self.peg_price(&order) {
+                Some(price) => {
+                    if let Err(err) = self.check_price_band(price) {
+                        self.statuses.remove(&order.order_id);
+                        return Err(err);
+                    }
+                    order.price = price;
+                    self.pegs.push(order.order_id.clone());
+                }
+                None => {
+                    self.dormant_pegs.push(order);
+                    return Ok(());
+                }
+            }
         }
 
-        #[test]
-        fn fak_rejected_when_price_doesnt_cross() {
-            let mut ob = Orderbook::new();
-            ob.add_order(sell_order("1".to_string(), 110, 50)).unwrap();
-            let result = ob.add_order(buy_fak("2".to_string(), 100, 50));
-            assert!(matches!(result, Err(OrderError::CantMatch)));
+        // Under auto-match the incoming order takes liquidity before any remainder rests
+        if self.auto_match && self.phase == BookPhase::Continuous {
+            self.take_liquidity(&mut order);
+            if !order.is_filled() {
+                self.rest_remainder(order);
+            }
+            self.place_bracket_children();
+            self.trigger_stops();
+        } else {
+            self.insert_order(order);
         }
 
-        #[test]
-        fn fak_accepted_when_can_match() {
-            let mut ob = Orderbook::new();
-            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-            let result = ob.add_order(buy_fak("2".to_string(), 100, 50));
-            assert!(result.is_ok());
+        self.publish_touch();
+        Ok(())
+    }
+
+    // References skip pegged orders so a peg never chases its own price
+    fn peg_price(&self, order: &Order) -> Option<Price> {
+        let target = match order.order_type {
+            OrderType::PeggedMid { offset } => {
+                let bid = self.reference_price(Side::Buy)?;
+                let ask = self.reference_price(Side::Sell)?;
+                self.mid_of(bid, ask).0.checked_add_signed(offset)?
+            }
+            // Buys step up from the bid and sells step down from the ask by the offset
+            OrderType::PeggedPrimary { offset } => {
+                let touch = self.reference_price(order.side)?.0;
+                match order.side {
+                    Side::Buy => touch.checked_add_signed(offset)?,
+                    Side::Sell => touch.checked_add_signed(offset.checked_neg()?)?,
+                }
+            }
+            _ => return None,
+        };
+        Some(Price::new(target)).filter(|&p| p != Price::max() && p != Price::min())
+    }
+
+    fn check_price_band(&self, price: Price) -> Result<(), OrderError> {
+        match self.price_band_limits() {
+            Some((low, high)) if price < low || price > high => Err(OrderError::OutsidePriceBand),
+            _ => Ok(()),
         }
+    }
 
-        mod fill_or_kill {
-            use super::*;
+    fn price_band_limits(&self) -> Option<(Price, Price)> {
+        let (reference, band) = (self.band_reference?, self.price_band?);
+        Some((
+            Price(reference.0.saturating_sub(band.0)),
+            Price(reference.0.saturating_add(band.0)),
+        ))
+    }
 
-            fn buy_fok(id: &str, p: u64, q: u64) -> Order {
-                Order::new(
-                    order_id(id),
-                    OrderType::FillOrKill,
-                    Side::Buy,
-                    price(p),
-                    qty(q),
-                )
+    fn reference_price(&self, side: Side) -> Option<Price> {
+        self.levels_best_first(side)
+            .find(|(_, orders)| orders.iter().any(|o| !o.order_type.is_pegged()))
+            .map(|(price, _)| price)
+    }
+
+    // Trades move the references pegs follow, so passes repeat until one trades nothing
+    fn reprice_pegs(&mut self) {
+        while self.reprice_pass() {}
+    }
+
+    // Only tracked peg ids are visited; ids whose orders have filled or cancelled drop out here
+    fn reprice_pass(&mut self) -> bool {
+        let before = self.trades.len();
+        let resting: Vec<(OrderId, Side, Price, Option<Price>)> = std::mem::take(&mut self.pegs)
+            .into_iter()
+            .filter_map(|order_id| {
+                let order = self.resting_order(order_id.clone())?;
+                let target = self.peg_price(order);
+                Some((order_id, order.side, order.price, target))
+            })
+            .collect();
+
+        for (order_id, side, price, target) in resting {
+            if target == Some(price) {
+                self.pegs.push(order_id);
+                continue;
+            }
+            let Some(mut order) = self.remove_from_level(side, price, order_id.clone()) else {
+                continue;
+            };
+            self.orders.delete(order_id);
+            match target {
+                Some(target) => {
+                    order.price = target;
+                    order.timestamp = self.clock.now();
+                    order.priority_time = None;
+                    self.next_sequence += 1;
+                    order.sequence = self.next_sequence;
+                    self.place_peg(order);
+                }
+                None => self.dormant_pegs.push(order),
             }
+        }
 
-            fn sell_fok(id: &str, p: u64, q: u64) -> Order {
-                Order::new(
-                    order_id(id),
-                    OrderType::FillOrKill,
-                    Side::Sell,
-                    price(p),
-                    qty(q),
-                )
+        for mut order in std::mem::take(&mut self.dormant_pegs) {
+            match self.peg_price(&order) {
+                Some(price) => {
+                    order.price = price;
+                    self.place_peg(order);
+                }
+                None => self.dormant_pegs.push(order),
             }
+        }
+        self.trades.len() > before
+    }
 
-            #[test]
-            fn fok_rejected_when_empty_book() {
-                let mut ob = Orderbook::new();
-                let result = ob.add_order(buy_fok("1", 100, 50));
-                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+    // A peg repriced through the opposing touch trades like an incoming order under auto-match.
+    // One repriced out of band is cancelled, as a resting order would be by enforce_price_bands.
+    fn place_peg(&mut self, mut order: Order) {
+        if self.check_price_band(order.price).is_err() {
+            self.statuses
+                .insert(order.order_id.clone(), OrderStatus::Cancelled);
+            self.events.push(BookEvent::OrderCancelled {
+                order_id: order.order_id,
+            });
+            return;
+        }
+        if self.auto_match
+            && self.phase == BookPhase::Continuous
+            && self.can_match(order.side, order.price)
+        {
+            self.take_liquidity(&mut order);
+            if order.is_filled() {
+                return;
             }
+        }
+        self.pegs.push(order.order_id.clone());
+        self.insert_order(order);
+    }
 
-            #[test]
-            fn fok_rejected_when_insufficient_quantity() {
-                let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 30)).unwrap();
-                let result = ob.add_order(buy_fok("2", 100, 50));
-                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+    // Compares against the last published touch so nested operations report each change once
+    fn publish_touch(&mut self) {
+        self.reprice_pegs();
+        let best_bid = self.best_price(Side::Buy);
+        let best_ask = self.best_price(Side::Sell);
+        let (old_bid, old_ask) = self.touch;
+
+        if let Some(new) = best_bid
+            && best_bid != old_bid
+        {
+            self.events.push(BookEvent::TouchImproved {
+                side: Side::Buy,
+                old: old_bid,
+                new,
+            });
+        }
+        if let Some(new) = best_ask
+            && best_ask != old_ask
+        {
+            self.events.push(BookEvent::TouchImproved {
+                side: Side::Sell,
+                old: old_ask,
+                new,
+            });
+        }
+        for (side, old, new) in [
+            (Side::Buy, old_bid, best_bid),
+            (Side::Sell, old_ask, best_ask),
+        ] {
+            if old.is_some() && new.is_none() {
+                self.events.push(BookEvent::SideEmptied { side });
             }
+        }
+        self.touch = (best_bid, best_ask);
+    }
 
-            #[test]
-            fn fok_rejected_when_price_doesnt_cross() {
-                let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 110, 100)).unwrap();
-                let result = ob.add_order(buy_fok("2", 100, 50));
-                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+    // The index and the levels are separate copies, so check both in case they drift apart
+    fn id_in_use(&self, order_id: &OrderId) -> bool {
+        self.orders.contains(order_id.clone())
+            || self.dormant_pegs.iter().any(|o| &o.order_id == order_id)
+            || self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .any(|level| level.contains(order_id.clone()))
+    }
+
+    fn take_liquidity(&mut self, aggressor: &mut Order) {
+        while !aggressor.is_filled() || self.replenish(aggressor) {
+            // Positions move with every fill, so reduce-only caps are re-read each pass
+            if aggressor.reduce_only {
+                let allowed = self.reducible_quantity(aggressor.participant_id, aggressor.side);
+                Self::trim_reduce_only(aggressor, allowed);
+                if aggressor.is_filled() {
+                    let status = if aggressor.filled_quantity() > Quantity(0) {
+                        OrderStatus::Filled
+                    } else {
+                        OrderStatus::Cancelled
+                    };
+                    self.statuses.insert(aggressor.order_id.clone(), status);
+                    break;
+                }
             }
 
-            #[test]
-            fn fok_accepted_when_can_fully_fill_exact() {
-                let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-                let result = ob.add_order(buy_fok("2", 100, 50));
-                assert!(result.is_ok());
+            let Some(level_price) = self.best_price(aggressor.side.opposite()) else {
+                break;
+            };
+            let (bid_price, ask_price) = match aggressor.side {
+                Side::Buy => (aggressor.price, level_price),
+                Side::Sell => (level_price, aggressor.price),
+            };
+            if !self.crosses(bid_price, ask_price) {
+                break;
+            }
+            if self.cap_reduce_only_front(aggressor.side.opposite(), level_price) {
+                continue;
+            }
+
+            let (maker_id, maker_participant, to_fill, maker_leaves, maker_done) = {
+                let level = match aggressor.side {
+                    Side::Buy => self.asks.get_mut(&level_price).unwrap(),
+                    Side::Sell => self.bids.get_mut(&Reverse(level_price)).unwrap(),
+                };
+                let maker = level.front_mut().unwrap();
+                let to_fill = std::cmp::min(aggressor.remaining_quantity, maker.remaining_quantity);
+                maker.fill(to_fill).ok();
+                aggressor.fill(to_fill).ok();
+                (
+                    maker.order_id.clone(),
+                    maker.participant_id,
+                    to_fill,
+                    maker.leaves_quantity(),
+                    maker.is_filled(),
+                )
+            };
+            self.update_position(aggressor.participant_id, aggressor.side, to_fill);
+            self.update_position(maker_participant, aggressor.side.opposite(), to_fill);
+
+            let trade_price = self.trade_price(bid_price, ask_price, aggressor.side);
+            let ((bid_id, bid_participant), (ask_id, ask_participant)) = match aggressor.side {
+                Side::Buy => (
+                    (aggressor.order_id.clone(), aggressor.participant_id),
+                    (maker_id.clone(), maker_participant),
+                ),
+                Side::Sell => (
+                    (maker_id.clone(), maker_participant),
+                    (aggressor.order_id.clone(), aggressor.participant_id),
+                ),
+            };
+            self.trades.push(Trade {
+                bid_trade: TradeInfo::new(bid_id, trade_price, to_fill)
+                    .with_participant(bid_participant),
+                ask_trade: TradeInfo::new(ask_id, trade_price, to_fill)
+                    .with_participant(ask_participant),
+                sequence: 0,
+                timestamp: self.clock.now(),
+                aggressor: aggressor.side,
+            });
+            self.total_filled = self.total_filled.saturating_add(to_fill.0);
+            self.record_fill(
+                aggressor.order_id.clone(),
+                to_fill,
+                aggressor.leaves_quantity(),
+            );
+            self.record_fill(maker_id.clone(), to_fill, maker_leaves);
+
+            if maker_done {
+                let maker = self.remove_from_level(
+                    aggressor.side.opposite(),
+                    level_price,
+                    maker_id.clone(),
+                );
+                self.orders.delete(maker_id);
+                if let Some(maker) = maker.filter(|m| m.hidden_quantity() > Quantity(0)) {
+                    self.requeue_iceberg(maker);
+                }
+            }
+        }
+    }
+
+    // Moves the next display slice out of reserve once the visible part is gone
+    fn replenish(&mut self, order: &mut Order) -> bool {
+        let Some(iceberg) = order.iceberg.as_mut() else {
+            return false;
+        };
+        if order.remaining_quantity > Quantity(0) || iceberg.hidden == Quantity(0) {
+            return false;
+        }
+
+        let slice = Quantity(
+            self.rng
+                .between(iceberg.display_min.0, iceberg.display_max.0)
+                .clamp(1, iceberg.hidden.0),
+        );
+        iceberg.hidden -= slice;
+        order.remaining_quantity = slice;
+        true
+    }
+
+    // A refilled slice is a fresh arrival and joins the back of its level
+    fn requeue_iceberg(&mut self, mut order: Order) {
+        self.replenish(&mut order);
+        order.timestamp = self.clock.now();
+        order.priority_time = None;
+        self.next_sequence += 1;
+        order.sequence = self.next_sequence;
+        self.insert_order(order);
+    }
+
+    fn rest_remainder(&mut self, mut order: Order) {
+        match order.order_type {
+            OrderType::FillAndKill | OrderType::FillOrKill | OrderType::Market => {
+                self.statuses
+                    .insert(order.order_id.clone(), OrderStatus::Cancelled);
+            }
+            OrderType::MarketToLimit => match self.last_fill_price(order.order_id.clone()) {
+                Some(limit_price) => {
+                    order.price = limit_price;
+                    order.order_type = OrderType::GoodTillCancelled;
+                    self.insert_order(order);
+                }
+                None => {
+                    self.statuses
+                        .insert(order.order_id.clone(), OrderStatus::Cancelled);
+                }
+            },
+            _ => self.insert_order(order),
+        }
+    }
+
+    // Rows replay like live flow: orders the book rejects are skipped and not counted
+    pub fn load_csv(&mut self, reader: impl Read) -> Result<usize, ImportError> {
+        let mut loaded = 0;
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line_number = index + 1;
+            let line = line?;
+            let row = line.trim();
+            if row.is_empty() || (line_number == 1 && row.starts_with("id,")) {
+                continue;
+            }
+            let order = Self::parse_csv_row(row, line_number)?;
+            if self.add_order(order).is_ok() {
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+
+    fn parse_csv_row(row: &str, line: usize) -> Result<Order, ImportError> {
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let [id, order_type, side, price, quantity] = fields[..] else {
+            return Err(ImportError::MalformedRow { line });
+        };
+
+        // Pegs take their price from the book, so their price column holds the signed offset
+        let peg_offset = || {
+            price
+                .parse()
+                .map_err(|_| ImportError::InvalidNumber { line })
+        };
+        let order_type = match order_type {
+            "PeggedMid" => OrderType::PeggedMid {
+                offset: peg_offset()?,
+            },
+            "PeggedPrimary" => OrderType::PeggedPrimary {
+                offset: peg_offset()?,
+            },
+            "GoodTillCancelled" => OrderType::GoodTillCancelled,
+            "FillAndKill" => OrderType::FillAndKill,
+            "FillOrKill" => OrderType::FillOrKill,
+            "GoodForDay" => OrderType::GoodForDay,
+            "Market" => OrderType::Market,
+            "MarketToLimit" => OrderType::MarketToLimit,
+            other => {
+                return Err(ImportError::UnknownOrderType {
+                    line,
+                    order_type: other.to_string(),
+                });
+            }
+        };
+        let side = match side {
+            "Buy" => Side::Buy,
+            "Sell" => Side::Sell,
+            other => {
+                return Err(ImportError::UnknownSide {
+                    line,
+                    side: other.to_string(),
+                });
+            }
+        };
+        let price = if order_type.is_pegged() {
+            Price::min()
+        } else {
+            Price::new(
+                price
+                    .parse()
+                    .map_err(|_| ImportError::InvalidNumber { line })?,
+            )
+        };
+        let quantity = quantity
+            .parse()
+            .map_err(|_| ImportError::InvalidNumber { line })?;
+
+        Ok(Order::new(
+            OrderId::new(id.to_string()),
+            order_type,
+            side,
+            price,
+            Quantity(quantity),
+        ))
+    }
+
+    pub fn generate_order_id(&mut self) -> OrderId {
+        loop {
+            self.next_auto_id += 1;
+            let order_id = OrderId::new(format!("auto-{}", self.next_auto_id));
+            if !self.orders.contains(order_id.clone()) && !self.statuses.contains_key(&order_id) {
+                return order_id;
+            }
+        }
+    }
+
+    pub fn quote(
+        &mut self,
+        bid_price: Price,
+        ask_price: Price,
+        size: Quantity,
+    ) -> Result<(OrderId, OrderId), OrderError> {
+        if self.crosses(bid_price, ask_price) {
+            return Err(OrderError::WouldCross);
+        }
+        if let Some(min_spread) = self.min_quote_spread
+            && bid_price.0.abs_diff(ask_price.0) < min_spread.0
+        {
+            return Err(OrderError::SpreadTooTight);
+        }
+
+        let bid_id = self.generate_order_id();
+        let ask_id = self.generate_order_id();
+        let bid = Order::new(
+            bid_id.clone(),
+            OrderType::GoodTillCancelled,
+            Side::Buy,
+            bid_price,
+            size,
+        );
+        let ask = Order::new(
+            ask_id.clone(),
+            OrderType::GoodTillCancelled,
+            Side::Sell,
+            ask_price,
+            size,
+        );
+
+        self.add_order(bid)?;
+        if let Err(err) = self.add_order(ask) {
+            let _ = self.cancel_resting(bid_id);
+            return Err(err);
+        }
+        Ok((bid_id, ask_id))
+    }
+
+    pub fn order_status(&self, order_id: OrderId) -> Option<OrderStatus> {
+        self.statuses.get(&order_id).copied()
+    }
+
+    fn ensure_not_filled(&self, order_id: &OrderId) -> Result<(), OrderError> {
+        match self.statuses.get(order_id) {
+            Some(OrderStatus::Filled) => Err(OrderError::AlreadyFilled),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert_order(&mut self, order: Order) {
+        self.events.push(BookEvent::OrderAdded {
+            order_id: order.order_id.clone(),
+            side: order.side,
+            price: order.price,
+            quantity: order.remaining_quantity,
+        });
+        self.orders.push_back(order.clone());
+        let level = match order.side {
+            Side::Buy => self.bids.entry(Reverse(order.price)).or_default(),
+            Side::Sell => self.asks.entry(order.price).or_default(),
+        };
+        // Matching always consumes from the front, so LIFO queues newcomers there
+        match (self.level_priority, order.priority_time) {
+            (LevelPriority::Fifo, None) => level.push_back(order),
+            (LevelPriority::Fifo, Some(priority_time)) => {
+                let index = level
+                    .iter()
+                    .position(|resting| resting.queue_time() > priority_time)
+                    .unwrap_or(level.len());
+                level.insert(index, order);
+            }
+            (LevelPriority::Lifo, _) => level.push_front(order),
+        }
+    }
+
+    pub fn modify_order(
+        &mut self,
+        order_id: OrderId,
+        new_quantity: Quantity,
+    ) -> Result<(), OrderError> {
+        self.ensure_not_filled(&order_id)?;
+        let (side, price) = {
+            let order = self
+                .orders
+                .get(order_id.clone())
+                .ok_or(OrderError::OrderNotFound)?;
+            (order.side, order.price)
+        };
+        // The cap applies to the order's lifetime size, fills included
+        let filled = self
+            .resting_order(order_id.clone())
+            .ok_or(OrderError::OrderNotFound)?
+            .filled_quantity();
+        if self
+            .max_order_quantity
+            .is_some_and(|max| filled + new_quantity > max)
+        {
+            return Err(OrderError::OrderTooLarge);
+        }
+        self.record_modification(&order_id)?;
+
+        // The level copy carries the fills, so update it and mirror into the index.
+        // new_quantity is the total left to fill; for icebergs the visible slice is kept
+        // where possible and the reserve takes the rest. initial_quantity moves with the
+        // new size so filled_quantity() is unchanged.
+        let updated = {
+            let order = self
+                .level_order_mut(side, price, order_id.clone())
+                .ok_or(OrderError::OrderNotFound)?;
+            let filled = order.filled_quantity();
+            match order.iceberg.as_mut() {
+                Some(iceberg) => {
+                    let visible = order.remaining_quantity.min(new_quantity);
+                    iceberg.hidden = new_quantity - visible;
+                    order.remaining_quantity = visible;
+                }
+                None => order.remaining_quantity = new_quantity,
+            }
+            order.initial_quantity = filled + new_quantity;
+            order.clone()
+        };
+
+        if let Some(o) = self.orders.get_mut(order_id.clone()) {
+            *o = updated;
+        }
+        self.rematch_modified(order_id);
+
+        Ok(())
+    }
+
+    pub fn modification_count(&self, order_id: OrderId) -> Option<u32> {
+        let count = self.modifications.get(&order_id).copied().unwrap_or(0);
+        self.orders.contains(order_id).then_some(count)
+    }
+
+    fn check_modification(&self, order_id: &OrderId) -> Result<(), OrderError> {
+        let count = self.modifications.get(order_id).copied().unwrap_or(0);
+        if self.max_modifications.is_some_and(|max| count >= max) {
+            return Err(OrderError::TooManyModifications);
+        }
+        Ok(())
+    }
+
+    fn record_modification(&mut self, order_id: &OrderId) -> Result<(), OrderError> {
+        self.check_modification(order_id)?;
+        *self.modifications.entry(order_id.clone()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    // A modified order is re-checked like a new arrival when auto-match is on
+    fn rematch_modified(&mut self, order_id: OrderId) {
+        if self.auto_match && self.phase == BookPhase::Continuous {
+            self.match_incoming(order_id);
+        }
+    }
+
+    // Only a pure size reduction keeps queue position; any other change re-queues
+    pub fn replace_order_preserve_priority(&mut self, order: Order) -> Result<(), OrderError> {
+        self.ensure_not_filled(&order.order_id)?;
+        // The level copy carries the fills, so compare against what is actually left
+        let amend_down = {
+            let existing = self
+                .resting_order(order.order_id.clone())
+                .ok_or(OrderError::OrderNotFound)?;
+            existing.side == order.side
+                && existing.price == order.price
+                && existing.order_type == order.order_type
+                && existing.participant_id == order.participant_id
+                && order.initial_quantity < existing.leaves_quantity()
+        };
+
+        if amend_down {
+            return self.modify_order(order.order_id, order.initial_quantity);
+        }
+        // Checked up front so a rejected replacement leaves the original resting
+        self.validate_with_id(&order, true)?;
+        self.cancel_order(order.order_id.clone())?;
+        self.add_order(order)
+    }
+
+    // A reprice is a new arrival at the new level, so the order loses its queue position
+    pub fn modify_order_price(
+        &mut self,
+        order_id: OrderId,
+        new_price: Price,
+    ) -> Result<(), OrderError> {
+        self.ensure_not_filled(&order_id)?;
+        if new_price == Price::max() || new_price == Price::min() {
+            return Err(OrderError::ReservedPrice);
+        }
+        let (side, price, post_only) = {
+            let order = self
+                .orders
+                .get(order_id.clone())
+                .ok_or(OrderError::OrderNotFound)?;
+            (order.side, order.price, order.post_only)
+        };
+        if new_price == price {
+            return Ok(());
+        }
+        self.check_price_band(new_price)?;
+        if post_only {
+            self.check_post_only(side, new_price, self.best_price(side.opposite()))?;
+        }
+        self.record_modification(&order_id)?;
+        self.requeue_at(side, price, order_id.clone(), new_price)?;
+        self.rematch_modified(order_id);
+        self.publish_touch();
+
+        Ok(())
+    }
+
+    fn requeue_at(
+        &mut self,
+        side: Side,
+        price: Price,
+        order_id: OrderId,
+        new_price: Price,
+    ) -> Result<(), OrderError> {
+        let mut order = self
+            .remove_from_level(side, price, order_id.clone())
+            .ok_or(OrderError::OrderNotFound)?;
+        self.orders.delete(order_id);
+
+        order.price = new_price;
+        order.timestamp = self.clock.now();
+        order.priority_time = None;
+        self.next_sequence += 1;
+        order.sequence = self.next_sequence;
+        self.insert_order(order);
+        Ok(())
+    }
+
+    // Every new price is checked before anything moves, so a bad offset leaves the book untouched
+    pub fn shift_participant_orders(
+        &mut self,
+        participant_id: u64,
+        offset: i64,
+    ) -> Result<(), OrderError> {
+        let mut moves = self
+            .orders
+            .iter()
+            .filter(|order| {
+                // Pegs take their price from the book and would snap straight back
+                order.participant_id == Some(participant_id)
+                    && !order.order_type.is_market()
+                    && !order.order_type.is_pegged()
+            })
+            .map(|order| {
+                let new_price = order
+                    .price
+                    .0
+                    .checked_add_signed(offset)
+                    .map(Price::new)
+                    .filter(|&p| p != Price::max() && p != Price::min())
+                    .ok_or(OrderError::ReservedPrice)?;
+                self.check_price_band(new_price)?;
+                self.check_modification(&order.order_id)?;
+                Ok((
+                    order.sequence,
+                    order.order_id.clone(),
+                    order.side,
+                    order.price,
+                    new_price,
+                ))
+            })
+            .collect::<Result<Vec<_>, OrderError>>()?;
+        if offset == 0 {
+            return Ok(());
+        }
+
+        // Post-only orders face the touch as it will be once every move has landed
+        let shifted: HashMap<OrderId, Price> = moves
+            .iter()
+            .map(|(_, order_id, _, _, new_price)| (order_id.clone(), *new_price))
+            .collect();
+        let touch_after = |side: Side| {
+            self.levels_best_first(side)
+                .flat_map(|(_, orders)| orders.iter())
+                .map(|order| shifted.get(&order.order_id).copied().unwrap_or(order.price))
+                .reduce(|best, price| {
+                    if self.ranks_ahead(side, price, best) {
+                        price
+                    } else {
+                        best
+                    }
+                })
+        };
+        for order in self.orders.iter().filter(|order| order.post_only) {
+            if let Some(&new_price) = shifted.get(&order.order_id) {
+                self.check_post_only(order.side, new_price, touch_after(order.side.opposite()))?;
+            }
+        }
+
+        // Requeue in arrival order so the participant's own orders keep their relative priority
+        moves.sort_by_key(|&(sequence, ..)| sequence);
+        for (_, order_id, side, price, new_price) in &moves {
+            self.record_modification(order_id)?;
+            self.requeue_at(*side, *price, order_id.clone(), *new_price)?;
+        }
+        for (_, order_id, ..) in moves {
+            self.rematch_modified(order_id);
+        }
+        self.publish_touch();
+        Ok(())
+    }
+
+    pub fn improve_order(&mut self, order_id: OrderId, ticks: u32) -> Result<(), OrderError> {
+        self.ensure_not_filled(&order_id)?;
+        let (side, price) = {
+            let order = self
+                .orders
+                .get(order_id.clone())
+                .ok_or(OrderError::OrderNotFound)?;
+            (order.side, order.price)
+        };
+
+        let step = self.tick_size.0.saturating_mul(u64::from(ticks));
+        let raise = matches!(
+            (side, self.price_priority),
+            (Side::Buy, PricePriority::Normal) | (Side::Sell, PricePriority::Inverted)
+        );
+        let new_price = if raise {
+            Price(price.0.saturating_add(step))
+        } else {
+            Price(price.0.saturating_sub(step))
+        };
+
+        let crosses = match side {
+            Side::Buy => self
+                .best_price(Side::Sell)
+                .is_some_and(|best_ask| self.crosses(new_price, best_ask)),
+            Side::Sell => self
+                .best_price(Side::Buy)
+                .is_some_and(|best_bid| self.crosses(best_bid, new_price)),
+        };
+        if crosses {
+            return Err(OrderError::WouldCross);
+        }
+
+        self.modify_order_price(order_id, new_price)
+    }
+
+    fn level_order_mut(
+        &mut self,
+        side: Side,
+        price: Price,
+        order_id: OrderId,
+    ) -> Option<&mut Order> {
+        match side {
+            Side::Buy => self.bids.get_mut(&Reverse(price))?.get_mut(order_id),
+            Side::Sell => self.asks.get_mut(&price)?.get_mut(order_id),
+        }
+    }
+
+    fn can_match(&self, side: Side, price: Price) -> bool {
+        match side {
+            Side::Buy => self
+                .best_price(Side::Sell)
+                .is_some_and(|ask_price| self.crosses(price, ask_price)),
+            Side::Sell => self
+                .best_price(Side::Buy)
+                .is_some_and(|bid_price| self.crosses(bid_price, price)),
+        }
+    }
+
+    pub fn can_fully_fill(&self, side: Side, price: Price, remaining_quantity: Quantity) -> bool {
+        let available = self
+            .fillable_levels(side, price)
+            .map(|(_, quantity)| quantity.0)
+            .sum::<u64>();
+        available >= remaining_quantity.0
+    }
+
+    // Prices each fill at the resting level, so non-maker trade price policies are not reflected
+    pub fn fok_fill_price(&self, side: Side, price: Price, quantity: Quantity) -> Option<Price> {
+        if quantity == Quantity(0) {
+            return None;
+        }
+        let mut needed = quantity.0;
+        let mut notional = 0u64;
+        for (level_price, available) in self.fillable_levels(side, price) {
+            let take = needed.min(available.0);
+            notional = notional.saturating_add(level_price.notional(Quantity(take)));
+            needed -= take;
+            if needed == 0 {
+                return Some(Price::new(notional / quantity.0));
+            }
+        }
+        None
+    }
+
+    // Twice the distance from the mid to the average price of buying size through the asks
+    pub fn effective_spread(&self, size: Quantity) -> Option<Price> {
+        let mid = self.midprice()?;
+        let average = self.fok_fill_price(Side::Buy, self.market_price(Side::Buy), size)?;
+        Some(Price::new(average.0.abs_diff(mid.0).saturating_mul(2)))
+    }
+
+    fn fillable_levels(&self, side: Side, price: Price) -> impl Iterator<Item = (Price, Quantity)> {
+        self.levels_best_first(side.opposite())
+            .take_while(move |&(p, _)| match side {
+                Side::Buy => self.crosses(price, p),
+                Side::Sell => self.crosses(p, price),
+            })
+            .map(|(p, orders)| (p, Self::level_quantity(orders)))
+    }
+
+    pub fn can_fill_order(&self, order: &Order) -> bool {
+        // Market orders accept any opposing price regardless of what the price field holds
+        let limit = if order.order_type.is_market() {
+            self.market_price(order.side)
+        } else {
+            order.price
+        };
+        self.can_fully_fill(order.side, limit, order.remaining_quantity)
+    }
+
+    pub fn has_liquidity(&self, side: Side) -> bool {
+        match side {
+            Side::Buy => !self.asks.is_empty(),
+            Side::Sell => !self.bids.is_empty(),
+        }
+    }
+
+    pub fn expire_due(&mut self, now: DateTime<Local>) -> usize {
+        self.cancel_where(|o| o.expires_at.is_some_and(|expires_at| expires_at <= now))
+    }
+
+    pub fn cancel_orders_older_than(&mut self, now: DateTime<Local>, max_age: Duration) -> usize {
+        let cutoff = now - max_age;
+        self.cancel_where(|o| o.timestamp < cutoff)
+    }
+
+    pub fn match_orders(&mut self) {
+        self.match_orders_limited(usize::MAX);
+    }
+
+    // Stops after max_trades even if still crossed; FAK/Market cleanup only runs once drained
+    pub fn match_orders_limited(&mut self, max_trades: usize) -> usize {
+        // Pre-open orders only queue; auction_uncross does the matching
+        if self.phase == BookPhase::PreOpen || self.kill_switch {
+            return 0;
+        }
+
+        self.expire_due(self.clock.now());
+
+        let mut produced = 0;
+        while produced < max_trades {
+            let Some((best_bid_price, best_ask_price)) = self.crossed_touch() else {
+                break;
+            };
+            if self.enforce_reduce_only(best_bid_price, best_ask_price) {
+                continue;
+            }
+            // Stop rather than spin if a malformed front pair neither fills nor leaves the book
+            let before = (self.trades.len(), self.orders.len(), self.level_count());
+            self.match_front_pair(best_bid_price, best_ask_price);
+            if (self.trades.len(), self.orders.len(), self.level_count()) == before {
+                break;
+            }
+            // Clearing an empty front makes progress without trading
+            produced += self.trades.len() - before.0;
+        }
+
+        // Resumed by the next call
+        if self.crossed_touch().is_some() {
+            self.publish_touch();
+            return produced;
+        }
+
+        // We need to remove FillAndKills with no other side
+        let bid_fak_ids: Vec<OrderId> = self
+            .levels_best_first(Side::Buy)
+            .next()
+            .map(|(_, orders)| {
+                orders
+                    .iter()
+                    .filter(|order| {
+                        matches!(order.order_type, OrderType::FillAndKill | OrderType::Market)
+                    })
+                    .map(|order| order.order_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let ask_fak_ids: Vec<OrderId> = self
+            .levels_best_first(Side::Sell)
+            .next()
+            .map(|(_, orders)| {
+                orders
+                    .iter()
+                    .filter(|order| {
+                        matches!(order.order_type, OrderType::FillAndKill | OrderType::Market)
+                    })
+                    .map(|order| order.order_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for id in bid_fak_ids.into_iter().chain(ask_fak_ids) {
+            let _ = self.cancel_resting(id);
+        }
+
+        // MarketToLimit remainders rest at their last fill price instead
+        let market_to_limit_ids: Vec<OrderId> = self
+            .levels_best_first(Side::Buy)
+            .next()
+            .into_iter()
+            .chain(self.levels_best_first(Side::Sell).next())
+            .map(|(_, orders)| orders)
+            .flat_map(|orders| orders.iter())
+            .filter(|order| order.order_type == OrderType::MarketToLimit)
+            .map(|order| order.order_id.clone())
+            .collect();
+
+        for id in market_to_limit_ids {
+            self.rest_market_to_limit(id);
+        }
+
+        self.place_bracket_children();
+        if self.trigger_stops() {
+            produced += self.match_orders_limited(max_trades - produced);
+        }
+        self.publish_touch();
+        produced
+    }
+
+    // Takes liquidity as an immediate-or-cancel order that never touches the book
+    pub fn sweep(&mut self, side: Side, quantity: Quantity, worst_price: Price) -> Vec<Trade> {
+        let order_id = self.generate_order_id();
+        let mut order = Order::new(
+            order_id.clone(),
+            OrderType::FillAndKill,
+            side,
+            worst_price,
+            quantity,
+        );
+        if self.phase != BookPhase::Continuous || self.validate(&order).is_err() {
+            return Vec::new();
+        }
+
+        order.timestamp = self.clock.now();
+        self.next_sequence += 1;
+        order.sequence = self.next_sequence;
+        self.statuses.insert(order_id.clone(), OrderStatus::Open);
+
+        let before = self.trades.len();
+        self.take_liquidity(&mut order);
+        if !order.is_filled() {
+            self.rest_remainder(order);
+        }
+        self.place_bracket_children();
+        self.trigger_stops();
+        self.publish_touch();
+
+        self.trades
+            .iter()
+            .skip(before)
+            .filter(|trade| {
+                trade.bid_trade.order_id() == order_id || trade.ask_trade.order_id() == order_id
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Other crossed pairs may also trade here, but only this order's fills are returned
+    pub fn add_and_match(&mut self, order: Order) -> Result<Vec<Trade>, OrderError> {
+        let order_id = order.order_id.clone();
+        let before = self.trades.len();
+        self.add_order(order)?;
+        self.match_orders();
+
+        Ok(self
+            .trades
+            .iter()
+            .skip(before)
+            .filter(|trade| {
+                trade.bid_trade.order_id() == order_id || trade.ask_trade.order_id() == order_id
+            })
+            .cloned()
+            .collect())
+    }
+
+    // Only this order takes liquidity; other crossed pairs are left for match_orders
+    pub fn match_incoming(&mut self, order_id: OrderId) -> Vec<Trade> {
+        if self.phase != BookPhase::Continuous || self.kill_switch {
+            return Vec::new();
+        }
+        let Some((side, price)) = self
+            .orders
+            .get(order_id.clone())
+            .map(|order| (order.side, order.price))
+        else {
+            return Vec::new();
+        };
+        let queue_position = match side {
+            Side::Buy => self.bids.get(&Reverse(price)),
+            Side::Sell => self.asks.get(&price),
+        }
+        .and_then(|orders| orders.position(order_id.clone()));
+        let Some(mut order) = self.remove_from_level(side, price, order_id.clone()) else {
+            return Vec::new();
+        };
+
+        let before = self.trades.len();
+        self.take_liquidity(&mut order);
+
+        if order.is_filled() {
+            self.orders.delete(order_id);
+        } else if order.order_type.is_market()
+            || matches!(
+                order.order_type,
+                OrderType::FillAndKill | OrderType::FillOrKill
+            )
+        {
+            self.orders.delete(order_id);
+            self.rest_remainder(order);
+        } else {
+            // Put the remainder back where it was queued
+            if let Some(o) = self.orders.get_mut(order_id) {
+                *o = order.clone();
+            }
+            let level = match side {
+                Side::Buy => self.bids.entry(Reverse(price)).or_default(),
+                Side::Sell => self.asks.entry(price).or_default(),
+            };
+            level.insert(queue_position.unwrap_or(0), order);
+        }
+
+        self.place_bracket_children();
+        self.publish_touch();
+        self.trades.iter().skip(before).cloned().collect()
+    }
+
+    // Opens continuous trading, executing the crossed pre-open interest at one clearing price
+    // An engaged kill switch keeps the auction open, since matching could not run anyway
+    pub fn auction_uncross(&mut self) -> Option<(Price, Quantity)> {
+        if self.phase != BookPhase::PreOpen || self.kill_switch {
+            return None;
+        }
+        self.phase = BookPhase::Continuous;
+        let (clearing_price, volume) = self.indicative_uncross()?;
+
+        self.auction_price = Some(clearing_price);
+        self.match_orders();
+        self.auction_price = None;
+
+        Some((clearing_price, volume))
+    }
+
+    pub fn matchable_volume_at(&self, price: Price) -> Quantity {
+        let (demand, supply) = self.auction_interest_at(price);
+        demand.min(supply)
+    }
+
+    // Bid quantity willing to pay at least price, ask quantity willing to sell at or below it
+    fn auction_interest_at(&self, price: Price) -> (Quantity, Quantity) {
+        let interest = |side: Side| {
+            self.levels_best_first(side)
+                .take_while(|&(p, _)| match side {
+                    Side::Buy => self.crosses(p, price),
+                    Side::Sell => self.crosses(price, p),
+                })
+                .map(|(_, orders)| Self::level_quantity(orders))
+                .fold(Quantity(0), |acc, q| acc + q)
+        };
+        (interest(Side::Buy), interest(Side::Sell))
+    }
+
+    // Picks the price with the most executable volume without touching the book. Ties go
+    // to the smaller buy/sell surplus, then to the price closest to the last trade, then to
+    // the lower price.
+    pub fn indicative_uncross(&self) -> Option<(Price, Quantity)> {
+        let candidates = self
+            .bids
+            .keys()
+            .map(|&Reverse(p)| p)
+            .chain(self.asks.keys().copied())
+            .filter(|&p| p != Price::max() && p != Price::min());
+
+        let reference = self.trades.last_price();
+        candidates
+            .map(|p| {
+                let (demand, supply) = self.auction_interest_at(p);
+                (p, demand.min(supply), demand.0.abs_diff(supply.0))
+            })
+            .filter(|&(_, volume, _)| volume > Quantity(0))
+            .min_by_key(|&(p, volume, surplus)| {
+                let distance = reference.map_or(0, |r| r.0.abs_diff(p.0));
+                (Reverse(volume), surplus, distance, p)
+            })
+            .map(|(p, volume, _)| (p, volume))
+    }
+
+    fn crossed_touch(&self) -> Option<(Price, Price)> {
+        let best_bid_price = self.best_price(Side::Buy)?;
+        let best_ask_price = self.best_price(Side::Sell)?;
+        self.crosses(best_bid_price, best_ask_price)
+            .then_some((best_bid_price, best_ask_price))
+    }
+
+    fn match_front_pair(&mut self, best_bid_price: Price, best_ask_price: Price) {
+        // Get order info and fill amount
+        let (
+            (bid_id, bid_participant),
+            (ask_id, ask_participant),
+            to_fill,
+            aggressor,
+            bid_remaining,
+            ask_remaining,
+        ) = {
+            let (Some(bid_order), Some(ask_order)) = (
+                self.bids
+                    .get_mut(&Reverse(best_bid_price))
+                    .and_then(|orders| orders.front_mut()),
+                self.asks
+                    .get_mut(&best_ask_price)
+                    .and_then(|orders| orders.front_mut()),
+            ) else {
+                return;
+            };
+
+            let to_fill = std::cmp::min(bid_order.remaining_quantity, ask_order.remaining_quantity);
+            bid_order.fill(to_fill).ok();
+            ask_order.fill(to_fill).ok();
+
+            // The later arrival is the one taking liquidity
+            let aggressor = if bid_order.sequence > ask_order.sequence {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+
+            (
+                (bid_order.order_id.clone(), bid_order.participant_id),
+                (ask_order.order_id.clone(), ask_order.participant_id),
+                to_fill,
+                aggressor,
+                bid_order.leaves_quantity(),
+                ask_order.leaves_quantity(),
+            )
+        }; // borrows end here
+        self.update_position(bid_participant, Side::Buy, to_fill);
+        self.update_position(ask_participant, Side::Sell, to_fill);
+
+        // Record trade; an empty front only needs clearing out below
+        if to_fill > Quantity(0) {
+            let trade_price = self.trade_price(best_bid_price, best_ask_price, aggressor);
+
+            self.trades.push(Trade {
+                bid_trade: TradeInfo::new(bid_id.clone(), trade_price, to_fill)
+                    .with_participant(bid_participant),
+                ask_trade: TradeInfo::new(ask_id.clone(), trade_price, to_fill)
+                    .with_participant(ask_participant),
+                sequence: 0,
+                timestamp: self.clock.now(),
+                aggressor,
+            });
+            self.total_filled = self.total_filled.saturating_add(to_fill.0);
+            self.record_fill(bid_id.clone(), to_fill, bid_remaining);
+            self.record_fill(ask_id.clone(), to_fill, ask_remaining);
+        }
+
+        // Remove filled orders and clean up empty levels
+        let mut refills = Vec::new();
+        if let Some(bid_orders) = self.bids.get_mut(&Reverse(best_bid_price)) {
+            if bid_orders.front().map(|o| o.is_filled()).unwrap_or(false) {
+                refills.extend(bid_orders.pop());
+                self.orders.delete(bid_id.clone());
+            }
+            if bid_orders.is_empty() {
+                self.bids.remove(&Reverse(best_bid_price));
+            }
+        }
+
+        if let Some(ask_orders) = self.asks.get_mut(&best_ask_price) {
+            if ask_orders.front().map(|o| o.is_filled()).unwrap_or(false) {
+                refills.extend(ask_orders.pop());
+                self.orders.delete(ask_id.clone());
+            }
+            if ask_orders.is_empty() {
+                self.asks.remove(&best_ask_price);
+            }
+        }
+
+        for order in refills {
+            if order.hidden_quantity() > Quantity(0) {
+                self.requeue_iceberg(order);
+            }
+        }
+    }
+
+    // Trims reduce-only fronts to the participant's opposing position. Returns true when an
+    // order was cancelled outright, since the touch may have moved.
+    fn enforce_reduce_only(&mut self, best_bid_price: Price, best_ask_price: Price) -> bool {
+        let bid_cancelled = self.cap_reduce_only_front(Side::Buy, best_bid_price);
+        let ask_cancelled = self.cap_reduce_only_front(Side::Sell, best_ask_price);
+        bid_cancelled || ask_cancelled
+    }
+
+    fn cap_reduce_only_front(&mut self, side: Side, price: Price) -> bool {
+        let front = match side {
+            Side::Buy => self.bids.get(&Reverse(price)),
+            Side::Sell => self.asks.get(&price),
+        }
+        .and_then(|orders| orders.front())
+        .filter(|order| order.reduce_only);
+        let Some(front) = front else {
+            return false;
+        };
+        let allowed = self.reducible_quantity(front.participant_id, side);
+        if front.leaves_quantity() <= allowed {
+            return false;
+        }
+
+        let order_id = front.order_id.clone();
+        if allowed == Quantity(0) {
+            return self.cancel_resting(order_id).is_ok();
+        }
+        if let Some(order) = self.level_order_mut(side, price, order_id.clone()) {
+            Self::trim_reduce_only(order, allowed);
+            let updated = order.clone();
+            if let Some(o) = self.orders.get_mut(order_id) {
+                *o = updated;
+            }
+        }
+        false
+    }
+
+    // The hidden reserve goes first so the visible slice keeps its place for as long as possible
+    fn trim_reduce_only(order: &mut Order, allowed: Quantity) {
+        let leaves = order.leaves_quantity();
+        if leaves <= allowed {
+            return;
+        }
+        let excess = leaves - allowed;
+        let from_hidden = match order.iceberg.as_mut() {
+            Some(iceberg) => {
+                let taken = iceberg.hidden.min(excess);
+                iceberg.hidden -= taken;
+                taken
+            }
+            None => Quantity(0),
+        };
+        order.remaining_quantity -= excess - from_hidden;
+        order.initial_quantity -= excess;
+    }
+
+    fn reducible_quantity(&self, participant_id: Option<u64>, side: Side) -> Quantity {
+        let position = participant_id
+            .and_then(|id| self.positions.get(&id))
+            .copied()
+            .unwrap_or(0);
+        match side {
+            Side::Buy if position < 0 => Quantity(position.unsigned_abs()),
+            Side::Sell if position > 0 => Quantity(position.unsigned_abs()),
+            _ => Quantity(0),
+        }
+    }
+
+    fn update_position(&mut self, participant_id: Option<u64>, side: Side, quantity: Quantity) {
+        let Some(participant_id) = participant_id else {
+            return;
+        };
+        let delta = i64::try_from(quantity.0).unwrap_or(i64::MAX);
+        let position = self.positions.entry(participant_id).or_insert(0);
+        *position = match side {
+            Side::Buy => position.saturating_add(delta),
+            Side::Sell => position.saturating_sub(delta),
+        };
+    }
+
+    pub fn add_bracket(
+        &mut self,
+        entry: Order,
+        take_profit: Price,
+        stop_loss: Price,
+    ) -> Result<(), OrderError> {
+        let valid = match entry.side {
+            Side::Buy => stop_loss < take_profit,
+            Side::Sell => stop_loss > take_profit,
+        };
+        if !valid {
+            return Err(OrderError::InvalidBracket);
+        }
+
+        let bracket = Bracket {
+            entry_id: entry.order_id.clone(),
+            side: entry.side,
+            quantity: entry.initial_quantity,
+            participant_id: entry.participant_id,
+            take_profit,
+            stop_loss,
+        };
+        self.add_order(entry)?;
+        self.brackets.push(bracket);
+        Ok(())
+    }
+
+    pub fn stop_orders(&self) -> impl Iterator<Item = &StopOrder> {
+        self.stops.iter()
+    }
+
+    // Entries that were cancelled or expired will never place children
+    fn prune_brackets(&mut self) {
+        let statuses = &self.statuses;
+        self.brackets.retain(|bracket| {
+            matches!(
+                statuses.get(&bracket.entry_id),
+                Some(OrderStatus::Open | OrderStatus::PartiallyFilled)
+            )
+        });
+    }
+
+    fn place_bracket_children(&mut self) {
+        self.prune_brackets();
+        for bracket in std::mem::take(&mut self.filled_brackets) {
+            let exit_side = bracket.side.opposite();
+            let entry_id = bracket.entry_id.as_str();
+            let take_profit_id = OrderId::new(format!("{entry_id}-tp"));
+            let stop_loss_id = OrderId::new(format!("{entry_id}-sl"));
+
+            let mut take_profit = Order::new(
+                take_profit_id.clone(),
+                OrderType::GoodTillCancelled,
+                exit_side,
+                bracket.take_profit,
+                bracket.quantity,
+            );
+            let mut stop_loss = Order::new(
+                stop_loss_id.clone(),
+                OrderType::Market,
+                exit_side,
+                bracket.stop_loss,
+                bracket.quantity,
+            );
+            take_profit.participant_id = bracket.participant_id;
+            stop_loss.participant_id = bracket.participant_id;
+
+            // The stop protects the filled entry even if the take-profit is rejected
+            let take_profit_placed = self.add_order(take_profit).is_ok();
+            self.stops.push(StopOrder {
+                order: stop_loss,
+                stop_price: bracket.stop_loss,
+            });
+            if take_profit_placed {
+                self.oco
+                    .insert(take_profit_id.clone(), stop_loss_id.clone());
+                self.oco.insert(stop_loss_id, take_profit_id);
+            }
+        }
+    }
+
+    fn trigger_stops(&mut self) -> bool {
+        let Some(last_price) = self.trades.last_price() else {
+            return false;
+        };
+
+        let (triggered, pending): (Vec<StopOrder>, Vec<StopOrder>) =
+            std::mem::take(&mut self.stops)
+                .into_iter()
+                .partition(|stop| stop.is_triggered(last_price));
+        self.stops = pending;
+
+        // The partner leg is only pulled once the stop is accepted; a rejected stop stays
+        // pending so the position is never left without either leg. It is retried on every
+        // pass, so the rejection is only reported the first time.
+        let mut submitted = false;
+        for stop in triggered {
+            let stop_id = stop.order.order_id.clone();
+            let partner = self.oco.get(&stop_id).cloned();
+            if let Err(reason) = self.accept_order(stop.order.clone()) {
+                if self.rejected_stops.insert(stop_id.clone()) {
+                    self.events.push(BookEvent::OrderRejected {
+                        order_id: stop_id,
+                        reason,
+                    });
+                }
+                self.stops.push(stop);
+                continue;
+            }
+            self.rejected_stops.remove(&stop_id);
+            submitted = true;
+            if let Some(partner) = partner {
+                self.oco.remove(&stop_id);
+                self.oco.remove(&partner);
+                let _ = self.cancel_resting(partner);
+            }
+        }
+        submitted
+    }
+
+    fn last_fill_price(&self, order_id: OrderId) -> Option<Price> {
+        self.trades
+            .iter()
+            .filter(|trade| {
+                trade.bid_trade.order_id() == order_id || trade.ask_trade.order_id() == order_id
+            })
+            .last()
+            .map(|trade| trade.price())
+    }
+
+    fn rest_market_to_limit(&mut self, order_id: OrderId) {
+        let Some(limit_price) = self.last_fill_price(order_id.clone()) else {
+            let _ = self.cancel_resting(order_id);
+            return;
+        };
+
+        let Some(side) = self.orders.get(order_id.clone()).map(|order| order.side) else {
+            return;
+        };
+        let sentinel = match side {
+            Side::Buy => Price::max(),
+            Side::Sell => Price::min(),
+        };
+        let Some(mut order) = self.remove_from_level(side, sentinel, order_id.clone()) else {
+            return;
+        };
+        self.orders.delete(order_id);
+
+        order.price = limit_price;
+        order.order_type = OrderType::GoodTillCancelled;
+        self.insert_order(order);
+    }
+
+    fn remove_from_level(&mut self, side: Side, price: Price, order_id: OrderId) -> Option<Order> {
+        match side {
+            Side::Buy => {
+                let orders = self.bids.get_mut(&Reverse(price))?;
+                let order = orders.remove(order_id);
+                if orders.is_empty() {
+                    self.bids.remove(&Reverse(price));
+                }
+                order
+            }
+            Side::Sell => {
+                let orders = self.asks.get_mut(&price)?;
+                let order = orders.remove(order_id);
+                if orders.is_empty() {
+                    self.asks.remove(&price);
+                }
+                order
+            }
+        }
+    }
+
+    fn record_fill(&mut self, order_id: OrderId, filled: Quantity, remaining: Quantity) {
+        let status = if remaining == Quantity(0) {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        self.statuses.insert(order_id.clone(), status);
+
+        let event = if remaining == Quantity(0) {
+            BookEvent::OrderFilled {
+                order_id: order_id.clone(),
+                filled,
+            }
+        } else {
+            BookEvent::OrderPartiallyFilled {
+                order_id: order_id.clone(),
+                filled,
+                remaining,
+            }
+        };
+        self.events.push(event);
+
+        if remaining == Quantity(0)
+            && let Some(pos) = self.brackets.iter().position(|b| b.entry_id == order_id)
+        {
+            let bracket = self.brackets.remove(pos);
+            self.filled_brackets.push(bracket);
+        }
+
+        // A full fill on one OCO leg pulls the pending stop on the other; a partial fill
+        // shrinks that stop to what the leg still has working
+        if remaining == Quantity(0) {
+            if let Some(partner) = self.oco.remove(&order_id) {
+                self.oco.remove(&partner);
+                self.stops.retain(|stop| stop.order.order_id != partner);
+            }
+        } else if let Some(partner) = self.oco.get(&order_id)
+            && let Some(stop) = self
+                .stops
+                .iter_mut()
+                .find(|stop| &stop.order.order_id == partner)
+        {
+            stop.order.initial_quantity = remaining;
+            stop.order.remaining_quantity = remaining;
+        }
+    }
+
+    fn trade_price(&self, bid_price: Price, ask_price: Price, aggressor: Side) -> Price {
+        if let Some(auction_price) = self.auction_price {
+            return auction_price;
+        }
+
+        // Market orders carry sentinel prices, so fall back to the other side's limit
+        if bid_price == self.market_price(Side::Buy) {
+            return ask_price;
+        }
+        if ask_price == self.market_price(Side::Sell) {
+            return bid_price;
+        }
+
+        let (maker_price, aggressor_price) = match aggressor {
+            Side::Buy => (ask_price, bid_price),
+            Side::Sell => (bid_price, ask_price),
+        };
+
+        match self.trade_price_policy {
+            TradePricePolicy::MakerPrice => maker_price,
+            TradePricePolicy::AggressorPrice => aggressor_price,
+            // Both use the crossing pair's limits rather than the book's touch; Midpoint
+            // predates the explicit PairMidpoint name
+            TradePricePolicy::Midpoint | TradePricePolicy::PairMidpoint => {
+                Price::new(bid_price.0.midpoint(ask_price.0))
+            }
+        }
+    }
+
+    // Hands back the level copy, which carries the fills made before the cancel
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<Order, OrderError> {
+        if let Some(min_resting_time) = self.min_resting_time
+            && let Some(order) = self.orders.get(order_id.clone())
+            && self.clock.now() - order.timestamp < min_resting_time
+        {
+            return Err(OrderError::MinRestingTimeNotMet);
+        }
+        self.cancel_resting(order_id)
+    }
+
+    // Book-driven cancels (expiry, IOC cleanup, pruning) are not subject to the resting minimum
+    fn cancel_resting(&mut self, order_id: OrderId) -> Result<Order, OrderError> {
+        self.ensure_not_filled(&order_id)?;
+        let located = self
+            .orders
+            .get(order_id.clone())
+            .map(|order| (order.side, order.price));
+
+        let cancelled = match located {
+            Some((side, price)) => {
+                let indexed = self.orders.remove(order_id.clone());
+                self.remove_from_level(side, price, order_id.clone())
+                    .or(indexed)
+                    .ok_or(OrderError::OrderNotFound)?
+            }
+            None => {
+                let position = self
+                    .dormant_pegs
+                    .iter()
+                    .position(|o| o.order_id == order_id)
+                    .ok_or(OrderError::OrderNotFound)?;
+                self.dormant_pegs.remove(position)
+            }
+        };
+        self.statuses
+            .insert(order_id.clone(), OrderStatus::Cancelled);
+        self.events.push(BookEvent::OrderCancelled { order_id });
+        self.prune_brackets();
+        self.publish_touch();
+        Ok(cancelled)
+    }
+
+    // Reads the level copies, which carry up-to-date fills, plus pegs waiting off-book, and
+    // cancels once iteration is done
+    pub fn cancel_where(&mut self, pred: impl Fn(&Order) -> bool) -> usize {
+        let ids: Vec<OrderId> = self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flat_map(|orders| orders.iter())
+            .chain(self.dormant_pegs.iter())
+            .filter(|o| pred(o))
+            .map(|o| o.order_id.clone())
+            .collect();
+
+        ids.into_iter()
+            .filter(|id| self.cancel_resting(id.clone()).is_ok())
+            .count()
+    }
+
+    pub fn cancel_participant(&mut self, participant_id: u64) -> usize {
+        self.cancel_where(|o| o.participant_id == Some(participant_id))
+    }
+
+    pub fn disconnect_participant(&mut self, participant_id: u64) -> usize {
+        self.disconnected.insert(participant_id);
+        self.cancel_participant(participant_id)
+    }
+
+    pub fn reconnect_participant(&mut self, participant_id: u64) {
+        self.disconnected.remove(&participant_id);
+    }
+
+    pub fn position(&self, participant_id: u64) -> i64 {
+        self.positions.get(&participant_id).copied().unwrap_or(0)
+    }
+
+    pub fn is_disconnected(&self, participant_id: u64) -> bool {
+        self.disconnected.contains(&participant_id)
+    }
+
+    pub fn get_levels(&self) -> OrderBookLevels {
+        let level_infos = |side| {
+            LevelInfos(
+                self.levels_best_first(side)
+                    .map(|(price, orders)| LevelInfo {
+                        price,
+                        quantity: Self::level_quantity(orders),
+                    })
+                    .collect(),
+            )
+        };
+        let bids = level_infos(Side::Buy);
+        let asks = level_infos(Side::Sell);
+
+        OrderBookLevels { bids, asks }
+    }
+
+    fn level_count(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+
+    pub fn level_summary(&self) -> Vec<(Side, Price, Quantity, usize)> {
+        [Side::Buy, Side::Sell]
+            .into_iter()
+            .flat_map(|side| {
+                self.levels_best_first(side).map(move |(price, orders)| {
+                    (side, price, Self::level_quantity(orders), orders.len())
+                })
+            })
+            .collect()
+    }
+
+    pub fn bid_levels(&self) -> usize {
+        self.bids.len()
+    }
+
+    pub fn ask_levels(&self) -> usize {
+        self.asks.len()
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn top_orders(&self, side: Side, n: usize) -> Vec<&Order> {
+        self.levels_best_first(side)
+            .flat_map(|(_, orders)| orders.iter())
+            .take(n)
+            .collect()
+    }
+
+    // Icebergs report their hidden reserve too, since it is still working
+    pub fn trade_context(&self, trade: &Trade) -> (Option<Quantity>, Option<Quantity>) {
+        let leaves = |order_id: OrderId| {
+            self.resting_order(order_id)
+                .map(|order| order.leaves_quantity())
+        };
+        (
+            leaves(trade.bid_trade.order_id()),
+            leaves(trade.ask_trade.order_id()),
+        )
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Option<Order> {
+        self.resting_order(order_id).cloned()
+    }
+
+    fn resting_order(&self, order_id: OrderId) -> Option<&Order> {
+        let (side, price) = self
+            .orders
+            .get(order_id.clone())
+            .map(|order| (order.side, order.price))?;
+        self.orders_at(side, price)?.get(order_id)
+    }
+
+    pub fn distinct_participants(&self) -> usize {
+        self.orders
+            .iter()
+            .filter_map(|order| order.participant_id)
+            .collect::<HashSet<u64>>()
+            .len()
+    }
+
+    pub fn orders_of_type(&self, order_type: OrderType) -> Vec<&Order> {
+        self.levels_best_first(Side::Buy)
+            .chain(self.levels_best_first(Side::Sell))
+            .flat_map(|(_, orders)| orders.iter())
+            .filter(|order| order.order_type == order_type)
+            .collect()
+    }
+
+    // Equal sizes keep book priority: better price first, then queue position
+    pub fn largest_orders(&self, side: Side, n: usize) -> Vec<&Order> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut orders: Vec<(usize, &Order)> = self
+            .levels_best_first(side)
+            .flat_map(|(_, orders)| orders.iter())
+            .enumerate()
+            .collect();
+        let by_size =
+            |&(position, order): &(usize, &Order)| (Reverse(order.remaining_quantity), position);
+        if n < orders.len() {
+            orders.select_nth_unstable_by_key(n - 1, by_size);
+            orders.truncate(n);
+        }
+        orders.sort_unstable_by_key(by_size);
+        orders.into_iter().map(|(_, order)| order).collect()
+    }
+
+    // Kept apart from LevelInfo so levels stay Copy and round-trip through the flat format
+    pub fn level_ends(&self, side: Side) -> Vec<(Price, OrderId, OrderId)> {
+        self.levels_best_first(side)
+            .filter_map(|(price, orders)| {
+                let front = orders.front()?.order_id.clone();
+                let back = orders.back()?.order_id.clone();
+                Some((price, front, back))
+            })
+            .collect()
+    }
+
+    pub fn orders_at(&self, side: Side, price: Price) -> Option<&Orders> {
+        match side {
+            Side::Buy => self.bids.get(&Reverse(price)),
+            Side::Sell => self.asks.get(&price),
+        }
+        .filter(|orders| !orders.is_empty())
+    }
+
+    // Level copies carry the fills, so snapshots read from the levels rather than the index
+    pub fn l3_snapshot(&self, side: Side) -> Vec<OrderSnapshot> {
+        self.levels_best_first(side)
+            .flat_map(|(_, orders)| orders.iter())
+            .map(|order| OrderSnapshot {
+                order_id: order.order_id.clone(),
+                side: order.side,
+                price: order.price,
+                initial_quantity: order.initial_quantity,
+                filled_quantity: order.filled_quantity(),
+                remaining_quantity: order.remaining_quantity,
+            })
+            .collect()
+    }
+
+    pub fn queue_ahead(&self, order_id: OrderId) -> Option<Quantity> {
+        let (side, price) = self
+            .orders
+            .get(order_id.clone())
+            .map(|order| (order.side, order.price))?;
+        let level = match side {
+            Side::Buy => self.bids.get(&Reverse(price))?,
+            Side::Sell => self.asks.get(&price)?,
+        };
+
+        Some(
+            level
+                .iter()
+                .take_while(|order| order.order_id != order_id)
+                .fold(Quantity(0), |acc, order| acc + order.remaining_quantity),
+        )
+    }
+
+    // Fraction of the queue ahead that the tape's volume at or through the order's price would clear
+    pub fn estimated_fill_fraction(&self, order_id: OrderId) -> Option<f64> {
+        let (side, price) = self
+            .orders
+            .get(order_id.clone())
+            .map(|order| (order.side, order.price))?;
+        let ahead = self.queue_ahead(order_id)?;
+        if ahead == Quantity(0) {
+            return Some(1.0);
+        }
+
+        let volume = self
+            .trades
+            .iter()
+            .filter(|trade| match side {
+                Side::Buy => trade.price() <= price,
+                Side::Sell => trade.price() >= price,
+            })
+            .fold(Quantity(0), |acc, trade| acc + trade.quantity());
+
+        Some((volume.0 as f64 / ahead.0 as f64).min(1.0))
+    }
+
+    // Inverted markets walk each map from the other end
+    fn levels_best_first(&self, side: Side) -> Box<dyn Iterator<Item = (Price, &Orders)> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bid_levels_iter()),
+            Side::Sell => Box::new(self.ask_levels_iter()),
+        }
+    }
+
+    // Walking from the back under inverted priority keeps these allocation-free
+    pub fn bid_levels_iter(&self) -> impl Iterator<Item = (Price, &Orders)> + '_ {
+        let inverted = self.price_priority == PricePriority::Inverted;
+        let mut levels = self.bids.iter();
+        std::iter::from_fn(move || {
+            if inverted {
+                levels.next_back()
+            } else {
+                levels.next()
+            }
+        })
+        .map(|(Reverse(p), o)| (*p, o))
+    }
+
+    pub fn ask_levels_iter(&self) -> impl Iterator<Item = (Price, &Orders)> + '_ {
+        let inverted = self.price_priority == PricePriority::Inverted;
+        let mut levels = self.asks.iter();
+        std::iter::from_fn(move || {
+            if inverted {
+                levels.next_back()
+            } else {
+                levels.next()
+            }
+        })
+        .map(|(p, o)| (*p, o))
+    }
+
+    fn best_price(&self, side: Side) -> Option<Price> {
+        self.levels_best_first(side).next().map(|(p, _)| p)
+    }
+
+    fn crosses(&self, bid_price: Price, ask_price: Price) -> bool {
+        match self.price_priority {
+            PricePriority::Normal => bid_price >= ask_price,
+            PricePriority::Inverted => bid_price <= ask_price,
+        }
+    }
+
+    fn ranks_ahead(&self, side: Side, price: Price, other: Price) -> bool {
+        price != other
+            && match side {
+                Side::Buy => self.crosses(price, other),
+                Side::Sell => self.crosses(other, price),
+            }
+    }
+
+    // The sentinel that ranks ahead of every limit price on this side
+    fn market_price(&self, side: Side) -> Price {
+        match (side, self.price_priority) {
+            (Side::Buy, PricePriority::Normal) | (Side::Sell, PricePriority::Inverted) => {
+                Price::max()
+            }
+            (Side::Sell, PricePriority::Normal) | (Side::Buy, PricePriority::Inverted) => {
+                Price::min()
+            }
+        }
+    }
+
+    pub fn quantity_at(&self, side: Side, price: Price) -> Quantity {
+        self.orders_at(side, price)
+            .map_or(Quantity(0), Self::level_quantity)
+    }
+
+    pub fn quantity_in_range(&self, side: Side, from: Price, to: Price) -> Quantity {
+        if from > to {
+            return Quantity(0);
+        }
+
+        let levels: Box<dyn Iterator<Item = &Orders>> = match side {
+            Side::Buy => Box::new(self.bids.range(Reverse(to)..=Reverse(from)).map(|(_, o)| o)),
+            Side::Sell => Box::new(self.asks.range(from..=to).map(|(_, o)| o)),
+        };
+
+        levels
+            .map(Self::level_quantity)
+            .fold(Quantity(0), |acc, q| Quantity(acc.0.saturating_add(q.0)))
+    }
+
+    pub fn quantity_to_reach(&self, side: Side, target: Price) -> Quantity {
+        // Everything strictly better than the target on the opposing side must be consumed
+        let opposite = side.opposite();
+        self.levels_best_first(opposite)
+            .take_while(|&(p, _)| self.ranks_ahead(opposite, p, target))
+            .map(|(_, orders)| Self::level_quantity(orders))
+            .fold(Quantity(0), |acc, q| acc + q)
+    }
+
+    // Market orders waiting at a sentinel price have no meaningful notional, so they are skipped
+    pub fn resting_notional(&self, side: Side) -> u64 {
+        self.levels_best_first(side)
+            .filter(|&(price, _)| price != Price::max() && price != Price::min())
+            .fold(0u64, |acc, (price, orders)| {
+                acc.saturating_add(price.notional(Self::level_quantity(orders)))
+            })
+    }
+
+    pub fn notional_to_sweep(&self, side: Side) -> u64 {
+        self.levels_best_first(side.opposite())
+            .fold(0u64, |acc, (price, orders)| {
+                acc.saturating_add(price.notional(Self::level_quantity(orders)))
+            })
+    }
+
+    fn level_quantity(orders: &Orders) -> Quantity {
+        orders
+            .iter()
+            .map(|order| order.remaining_quantity)
+            .fold(Quantity(0), |acc, q| Quantity(acc.0.saturating_add(q.0)))
+    }
+
+    pub fn simulate_match(&self) -> Vec<Trade> {
+        let mut copy = self.clone();
+        copy.clear_trades();
+        copy.match_orders();
+        copy.trades.iter().cloned().collect()
+    }
+
+    // The tape is already in sequence order, so no sort is needed
+    pub fn fills_for(&self, order_id: OrderId) -> Vec<(u64, Price, Quantity)> {
+        self.trades
+            .iter()
+            .filter(|trade| {
+                trade.bid_trade.order_id() == order_id || trade.ask_trade.order_id() == order_id
+            })
+            .map(|trade| (trade.sequence, trade.price(), trade.quantity()))
+            .collect()
+    }
+
+    // Only trades still on the tape count, and fees use the schedule in force now
+    pub fn session_pnl(&self, participant_id: u64) -> SessionPnl {
+        let mut pnl = SessionPnl {
+            volume: Quantity(0),
+            notional: 0,
+            fees_paid: 0,
+            net: 0,
+        };
+        for trade in self.trades.iter() {
+            let legs = [
+                (&trade.bid_trade, Side::Buy),
+                (&trade.ask_trade, Side::Sell),
+            ];
+            for (leg, side) in legs {
+                if leg.participant_id() != Some(participant_id) {
+                    continue;
+                }
+                let notional = leg.price().notional(leg.quantity());
+                let fee = self.fee_schedule.fee(notional, side == trade.aggressor);
+                let signed = i64::try_from(notional).unwrap_or(i64::MAX);
+                pnl.volume += leg.quantity();
+                pnl.notional = pnl.notional.saturating_add(notional);
+                pnl.fees_paid = pnl.fees_paid.saturating_add(fee);
+                pnl.net = match side {
+                    Side::Buy => pnl.net.saturating_sub(signed),
+                    Side::Sell => pnl.net.saturating_add(signed),
+                }
+                .saturating_sub(fee);
+            }
+        }
+        pnl
+    }
+
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.trades.last_price()
+    }
+
+    pub fn trades(&self) -> &Trades {
+        &self.trades
+    }
+
+    // Unlike the tape, this counter is not affected by clear_trades
+    pub fn total_filled_quantity(&self) -> u64 {
+        self.total_filled
+    }
+
+    pub fn clear_trades(&mut self) {
+        self.trades.clear();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.bids.retain(|_, orders| !orders.is_empty());
+        self.asks.retain(|_, orders| !orders.is_empty());
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            orders.shrink_to_fit();
+        }
+        self.orders.shrink_to_fit();
+        self.trades.shrink_to_fit();
+        self.events.shrink_to_fit();
+    }
+
+    // Configuration (clock, policies, market close) and the prune thread survive a reset
+    pub fn reset(&mut self) {
+        self.reset_book_keep_trades();
+        self.trades = Trades::new();
+        self.positions.clear();
+        self.total_filled = 0;
+        self.next_auto_id = 0;
+    }
+
+    // Positions and the auto-id counter stay with the tape so its order ids are never reused
+    pub fn reset_book_keep_trades(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.orders = Orders::new();
+        self.events.clear();
+        self.next_sequence = 0;
+        self.disconnected.clear();
+        self.brackets.clear();
+        self.filled_brackets.clear();
+        self.stops.clear();
+        self.dormant_pegs.clear();
+        self.pegs.clear();
+        self.oco.clear();
+        self.statuses.clear();
+        self.modifications.clear();
+        self.touch = (None, None);
+    }
+
+    pub fn statistics(&self) -> BookStats {
+        BookStats {
+            total_trades: self.trades.len(),
+            total_volume: self.trades.total_volume(),
+            total_notional: self.trades.total_notional(),
+            vwap: self.trades.vwap(),
+            high: self.trades.high(),
+            low: self.trades.low(),
+            last: self.trades.last_price(),
+            bid_levels: self.bid_levels(),
+            ask_levels: self.ask_levels(),
+            open_orders: self.order_count(),
+        }
+    }
+
+    pub fn events(&self) -> &[BookEvent] {
+        &self.events
+    }
+
+    pub fn drain_events(&mut self) -> Vec<BookEvent> {
+        std::mem::take(&mut self.events)
+    }
+    pub fn prune_good_for_day_orders(&mut self) {
+        self.cancel_where(|o| o.order_type == OrderType::GoodForDay);
+    }
+
+    pub fn run_gfd_prune_at(&mut self, now: DateTime<Local>) {
+        if now.time() >= self.market_close {
+            self.prune_good_for_day_orders();
+        }
+    }
+
+    pub fn start_prune_thread(orderbook: &Arc<Mutex<Self>>) {
+        let mut ob = orderbook.lock().unwrap_or_else(|e| e.into_inner());
+        if ob.prune.handle.is_some() {
+            return;
+        }
+
+        // Hold a weak handle so the thread doesn't keep the book alive
+        let weak = Arc::downgrade(orderbook);
+        let shutdown = Arc::clone(&ob.prune.shutdown);
+        let shutdown_cv = Arc::clone(&ob.prune.shutdown_cv);
+        ob.prune.handle = Some(std::thread::spawn(move || {
+            Self::run_prune_thread(weak, shutdown, shutdown_cv)
+        }));
+    }
+
+    fn run_prune_thread(
+        orderbook: Weak<Mutex<Self>>,
+        shutdown: Arc<AtomicBool>,
+        shutdown_cv: Arc<(Mutex<()>, Condvar)>,
+    ) {
+        loop {
+            // Read the close each pass so set_market_close applies to the next wait. The
+            // owner may be shutting us down while holding the book, so a busy book is
+            // retried shortly instead of blocking on it.
+            let market_close = {
+                let Some(book) = orderbook.upgrade() else {
+                    return;
+                };
+                match book.try_lock() {
+                    Ok(ob) => Some(ob.market_close),
+                    Err(TryLockError::Poisoned(e)) => Some(e.into_inner().market_close),
+                    Err(TryLockError::WouldBlock) => None,
+                }
+            };
+
+            let wait_duration = match market_close {
+                Some(market_close) => {
+                    let now = Local::now();
+                    let today_close = now.date_naive().and_time(market_close);
+
+                    let next_close = if now.time() >= market_close {
+                        today_close + Duration::days(1)
+                    } else {
+                        today_close
+                    };
+
+                    (next_close - now.naive_local())
+                        .to_std()
+                        .unwrap_or(std::time::Duration::from_millis(100))
+                        + std::time::Duration::from_millis(100)
+                }
+                None => PRUNE_BUSY_RETRY,
+            };
+
+            // Wait until market close or shutdown
+            {
+                let (lock, cvar) = &*shutdown_cv;
+                let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                let _ = cvar
+                    .wait_timeout_while(guard, wait_duration, |_| !shutdown.load(Ordering::Acquire))
+                    .unwrap_or_else(|e| e.into_inner());
+
+                if shutdown.load(Ordering::Acquire) {
+                    return;
+                }
+            }
+            if market_close.is_none() {
+                continue;
+            }
+
+            let Some(orderbook) = orderbook.upgrade() else {
+                return;
+            };
+
+            orderbook
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .run_gfd_prune_at(Local::now());
+        }
+    }
+
+    fn signal_shutdown(&self) {
+        self.prune.shutdown.store(true, Ordering::Release);
+        let (lock, cvar) = &*self.prune.shutdown_cv;
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        cvar.notify_all();
+    }
+
+    pub fn shutdown(&mut self) {
+        self.signal_shutdown();
+
+        if let Some(handle) = self.prune.handle.take() {
+            handle.join().ok();
+        }
+    }
+
+    pub fn shutdown_timeout(&mut self, timeout: std::time::Duration) -> Result<(), ShutdownError> {
+        self.signal_shutdown();
+
+        let Some(handle) = self.prune.handle.take() else {
+            return Ok(());
+        };
+
+        // The prune thread may drop the last handle to the book, so never wait on ourselves
+        if handle.thread().id() == std::thread::current().id() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + timeout;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                self.prune.handle = Some(handle);
+                return Err(ShutdownError::Timeout);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        handle.join().map_err(|_| ShutdownError::ThreadPanicked)
+    }
+}
+
+impl Default for Orderbook {
+    fn default() -> Self {
+        Orderbook::new()
+    }
+}
+
+impl Drop for Orderbook {
+    fn drop(&mut self) {
+        // A wedged prune thread is detached rather than hanging the caller
+        let _ = self.shutdown_timeout(DROP_SHUTDOWN_TIMEOUT);
+    }
+}
+
+impl OrderBookLevels {
+    pub fn bids(&self) -> &[LevelInfo] {
+        &self.bids.0
+    }
+    pub fn asks(&self) -> &[LevelInfo] {
+        &self.asks.0
+    }
+
+    // Bids come first, then asks, each in the order they are held
+    pub fn to_flat(&self) -> Vec<(Side, Price, Quantity)> {
+        let tagged = |side, levels: &LevelInfos| {
+            levels
+                .0
+                .iter()
+                .map(move |level| (side, level.price, level.quantity))
+                .collect::<Vec<_>>()
+        };
+        let mut flat = tagged(Side::Buy, &self.bids);
+        flat.extend(tagged(Side::Sell, &self.asks));
+        flat
+    }
+
+    pub fn from_flat(flat: &[(Side, Price, Quantity)]) -> OrderBookLevels {
+        let side_levels = |wanted| {
+            LevelInfos(
+                flat.iter()
+                    .filter(|&&(side, _, _)| side == wanted)
+                    .map(|&(_, price, quantity)| LevelInfo { price, quantity })
+                    .collect(),
+            )
+        };
+        OrderBookLevels {
+            bids: side_levels(Side::Buy),
+            asks: side_levels(Side::Sell),
+        }
+    }
+}
+
+impl LevelInfo {
+    pub fn price(&self) -> Price {
+        self.price
+    }
+    pub fn quantity(&self) -> Quantity {
+        self.quantity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Price;
+    use chrono::TimeZone;
+
+    fn price(p: u64) -> Price {
+        Price::new(p)
+    }
+
+    fn qty(q: u64) -> Quantity {
+        Quantity(q)
+    }
+
+    fn order_id(id: &str) -> OrderId {
+        OrderId::new(id.to_string())
+    }
+
+    fn buy_order(id: String, p: u64, q: u64) -> Order {
+        Order::new(
+            OrderId::new(id),
+            OrderType::GoodTillCancelled,
+            Side::Buy,
+            price(p),
+            qty(q),
+        )
+    }
+
+    fn sell_order(id: String, p: u64, q: u64) -> Order {
+        Order::new(
+            OrderId::new(id),
+            OrderType::GoodTillCancelled,
+            Side::Sell,
+            price(p),
+            qty(q),
+        )
+    }
+
+    fn market_buy(id: String, q: u64) -> Order {
+        Order::new(
+            OrderId::new(id),
+            OrderType::Market,
+            Side::Buy,
+            Price::max(),
+            qty(q),
+        )
+    }
+
+    fn buy_fak(id: String, p: u64, q: u64) -> Order {
+        Order::new(
+            OrderId::new(id),
+            OrderType::FillAndKill,
+            Side::Buy,
+            price(p),
+            qty(q),
+        )
+    }
+
+    // Resting and touch events now share the stream; these tests only look at fills
+    fn fill_events(events: &[BookEvent]) -> Vec<BookEvent> {
+        events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    BookEvent::OrderFilled { .. } | BookEvent::OrderPartiallyFilled { .. }
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn mock_clock() -> (Clock, Arc<Mutex<DateTime<Local>>>) {
+        let start = Local.with_ymd_and_hms(2024, 1, 2, 9, 30, 0).unwrap();
+        let time = Arc::new(Mutex::new(start));
+        let shared = Arc::clone(&time);
+        (Clock::new(move || *shared.lock().unwrap()), time)
+    }
+
+    mod orderbook {
+        use super::*;
+
+        #[test]
+        fn new_orderbook_is_empty() {
+            let ob = Orderbook::new();
+            let levels = ob.get_levels();
+            assert!(levels.bids.0.is_empty());
+            assert!(levels.asks.0.is_empty());
+        }
+
+        #[test]
+        fn add_buy_order() {
+            let mut ob = Orderbook::new();
+            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+            let levels = ob.get_levels();
+            assert_eq!(levels.bids.0.len(), 1);
+            assert_eq!(levels.bids.0[0].price, price(100));
+            assert_eq!(levels.bids.0[0].quantity, qty(50));
+        }
+
+        #[test]
+        fn add_sell_order() {
+            let mut ob = Orderbook::new();
+            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+            let levels = ob.get_levels();
+            assert_eq!(levels.asks.0.len(), 1);
+            assert_eq!(levels.asks.0[0].price, price(100));
+            assert_eq!(levels.asks.0[0].quantity, qty(50));
+        }
+
+        #[test]
+        fn add_duplicate_id_fails() {
+            let mut ob = Orderbook::new();
+            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+            let result = ob.add_order(buy_order("1".to_string(), 110, 60));
+            assert!(matches!(result, Err(OrderError::IdExists)));
+        }
+
+        #[test]
+        fn add_multiple_orders_same_price() {
+            let mut ob = Orderbook::new();
+            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+            ob.add_order(buy_order("2".to_string(), 100, 30)).unwrap();
+            let levels = ob.get_levels();
+            assert_eq!(levels.bids.0.len(), 1);
+            assert_eq!(levels.bids.0[0].quantity, qty(80));
+        }
+
+        #[test]
+        fn add_multiple_orders_different_prices() {
+            let mut ob = Orderbook::new();
+            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+            ob.add_order(buy_order("2".to_string(), 110, 30)).unwrap();
+            ob.add_order(buy_order("3".to_string(), 90, 20)).unwrap();
+            let levels = ob.get_levels();
+            assert_eq!(levels.bids.0.len(), 3);
+            assert_eq!(levels.bids.0[0].price, price(110));
+            assert_eq!(levels.bids.0[1].price, price(100));
+            assert_eq!(levels.bids.0[2].price, price(90));
+        }
+
+        #[test]
+        fn asks_sorted_low_to_high() {
+            let mut ob = Orderbook::new();
+            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+            ob.add_order(sell_order("2".to_string(), 110, 30)).unwrap();
+            ob.add_order(sell_order("3".to_string(), 90, 20)).unwrap();
+            let levels = ob.get_levels();
+            assert_eq!(levels.asks.0.len(), 3);
+            assert_eq!(levels.asks.0[0].price, price(90));
+            assert_eq!(levels.asks.0[1].price, price(100));
+            assert_eq!(levels.asks.0[2].price, price(110));
+        }
+
+        #[test]
+        fn can_match_buy_when_ask_exists_at_or_below() {
+            let mut ob = Orderbook::new();
+            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+            assert!(ob.can_match(Side::Buy, price(100)));
+            assert!(ob.can_match(Side::Buy, price(110)));
+            assert!(!ob.can_match(Side::Buy, price(90)));
+        }
+
+        #[test]
+        fn can_match_sell_when_bid_exists_at_or_above() {
+            let mut ob = Orderbook::new();
+            ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+            assert!(ob.can_match(Side::Sell, price(100)));
+            assert!(ob.can_match(Side::Sell, price(90)));
+            assert!(!ob.can_match(Side::Sell, price(110)));
+        }
+
+        #[test]
+        fn can_match_false_on_empty_book() {
+            let ob = Orderbook::new();
+            assert!(!ob.can_match(Side::Buy, price(100)));
+            assert!(!ob.can_match(Side::Sell, price(100)));
+        }
+
+        #[test]
+        fn fak_rejected_when_no_match_possible() {
+            let mut ob = Orderbook::new();
+            let result = ob.add_order(buy_fak("1".to_string(), 100, 50));
+            assert!(matches!(result, Err(OrderError::CantMatch)));
+        }
+
+        #[test]
+        fn fak_rejected_when_price_doesnt_cross() {
+            let mut ob = Orderbook::new();
+            ob.add_order(sell_order("1".to_string(), 110, 50)).unwrap();
+            let result = ob.add_order(buy_fak("2".to_string(), 100, 50));
+            assert!(matches!(result, Err(OrderError::CantMatch)));
+        }
+
+        #[test]
+        fn fak_accepted_when_can_match() {
+            let mut ob = Orderbook::new();
+            ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+            let result = ob.add_order(buy_fak("2".to_string(), 100, 50));
+            assert!(result.is_ok());
+        }
+
+        mod fill_or_kill {
+            use super::*;
+
+            fn buy_fok(id: &str, p: u64, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::FillOrKill,
+                    Side::Buy,
+                    price(p),
+                    qty(q),
+                )
+            }
+
+            fn sell_fok(id: &str, p: u64, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::FillOrKill,
+                    Side::Sell,
+                    price(p),
+                    qty(q),
+                )
+            }
+
+            #[test]
+            fn can_fill_order_when_depth_suffices() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                assert!(ob.can_fill_order(&buy_fok("3", 101, 10)));
+                assert!(!ob.can_fill_order(&buy_fok("3", 100, 10)));
+            }
+
+            #[test]
+            fn can_fill_order_rejects_short_depth() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                assert!(!ob.can_fill_order(&sell_fok("2", 99, 6)));
+            }
+
+            #[test]
+            fn can_fill_order_ignores_market_price_field() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                let mut market = Order::new(
+                    order_id("2"),
+                    OrderType::Market,
+                    Side::Sell,
+                    Price::min(),
+                    qty(5),
+                );
+                market.price = price(500);
+                assert!(ob.can_fill_order(&market));
+            }
+
+            #[test]
+            fn fok_rejected_when_empty_book() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(buy_fok("1", 100, 50));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+            }
+
+            #[test]
+            fn fok_rejected_when_insufficient_quantity() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 30)).unwrap();
+                let result = ob.add_order(buy_fok("2", 100, 50));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+            }
+
+            #[test]
+            fn fok_rejected_when_price_doesnt_cross() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 110, 100)).unwrap();
+                let result = ob.add_order(buy_fok("2", 100, 50));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+            }
+
+            #[test]
+            fn fok_accepted_when_can_fully_fill_exact() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                let result = ob.add_order(buy_fok("2", 100, 50));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn fok_accepted_when_can_fully_fill_excess_liquidity() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 100)).unwrap();
+                let result = ob.add_order(buy_fok("2", 100, 50));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn fok_accepted_across_multiple_price_levels() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 20)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 20)).unwrap();
+                let result = ob.add_order(buy_fok("4", 102, 50));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn fok_sell_rejected_when_insufficient_bids() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 30)).unwrap();
+                let result = ob.add_order(sell_fok("2", 100, 50));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+            }
+
+            #[test]
+            fn fok_sell_accepted_when_sufficient_bids() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
+                let result = ob.add_order(sell_fok("3", 99, 75));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn can_fully_fill_checks_price_constraint() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 50)).unwrap();
+                let result = ob.add_order(buy_fok("3", 100, 75));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+            }
+        }
+
+        mod market_order {
+            use super::*;
+
+            fn buy_market(id: &str, q: u64) -> Order {
+                Order::new(order_id(id), OrderType::Market, Side::Buy, price(0), qty(q))
+            }
+
+            fn sell_market(id: &str, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::Market,
+                    Side::Sell,
+                    price(0),
+                    qty(q),
+                )
+            }
+
+            #[test]
+            fn market_buy_rejected_when_no_liquidity() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(buy_market("1", 50));
+                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+            }
+
+            #[test]
+            fn market_sell_rejected_when_no_liquidity() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(sell_market("1", 50));
+                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+            }
+
+            #[test]
+            fn market_buy_accepted_when_asks_exist() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                let result = ob.add_order(buy_market("2", 50));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn market_sell_accepted_when_bids_exist() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                let result = ob.add_order(sell_market("2", 50));
+                assert!(result.is_ok());
+            }
+
+            #[test]
+            fn market_buy_uses_max_price() {
+                let order = buy_market("1", 50);
+                assert_eq!(order.price, Price::max());
+            }
+
+            #[test]
+            fn market_sell_uses_min_price() {
+                let order = sell_market("1", 50);
+                assert_eq!(order.price, Price::min());
+            }
+
+            #[test]
+            fn market_order_ignores_specified_price() {
+                let order = Order::new(
+                    order_id("1"),
+                    OrderType::Market,
+                    Side::Buy,
+                    price(100),
+                    qty(50),
+                );
+                assert_eq!(order.price, Price::max());
+            }
+        }
+
+        mod prune_good_for_day {
+            use super::*;
+
+            fn buy_gfd(id: &str, p: u64, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::GoodForDay,
+                    Side::Buy,
+                    price(p),
+                    qty(q),
+                )
+            }
+
+            fn sell_gfd(id: &str, p: u64, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::GoodForDay,
+                    Side::Sell,
+                    price(p),
+                    qty(q),
+                )
+            }
+
+            #[test]
+            fn prune_empty_book_no_panic() {
+                let mut ob = Orderbook::new();
+                ob.prune_good_for_day_orders();
+                assert!(ob.get_levels().bids.is_empty());
+                assert!(ob.get_levels().asks.is_empty());
+            }
+
+            #[test]
+            fn prune_removes_gfd_bids() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+                ob.prune_good_for_day_orders();
+                assert!(ob.get_levels().bids.is_empty());
+            }
+
+            #[test]
+            fn prune_removes_gfd_asks() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_gfd("1", 100, 50)).unwrap();
+                ob.add_order(sell_gfd("2", 101, 50)).unwrap();
+                ob.prune_good_for_day_orders();
+                assert!(ob.get_levels().asks.is_empty());
+            }
+
+            #[test]
+            fn prune_leaves_gtc_orders() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 50)).unwrap();
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.asks.len(), 1);
+            }
+
+            #[test]
+            fn prune_mixed_gtc_and_gfd() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 98, 50)).unwrap();
+                ob.add_order(buy_gfd("4", 97, 50)).unwrap();
+
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 2);
+                assert_eq!(levels.bids[0].price(), price(100));
+                assert_eq!(levels.bids[1].price(), price(98));
+            }
+
+            #[test]
+            fn prune_same_price_level_mixed() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_gfd("2", 100, 20)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 30)).unwrap();
+
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].quantity(), qty(40));
+            }
+
+            #[test]
+            fn prune_leaves_fak_orders() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 100)).unwrap();
+                ob.add_order(buy_fak("2".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+
+                ob.add_order(buy_gfd("3", 90, 50)).unwrap();
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+            }
+
+            #[test]
+            fn prune_leaves_fok_orders() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(100));
+            }
+
+            #[test]
+            fn prune_after_partial_fill() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 30)).unwrap();
+                ob.add_order(buy_gfd("2", 100, 50)).unwrap();
+                ob.match_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids[0].quantity(), qty(20));
+
+                ob.prune_good_for_day_orders();
+
+                assert!(ob.get_levels().bids.is_empty());
+            }
+
+            #[test]
+            fn prune_cleans_up_empty_price_levels() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+                ob.add_order(buy_gfd("3", 98, 50)).unwrap();
+
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+            }
+
+            #[test]
+            fn prune_multiple_times_is_idempotent() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+
+                ob.prune_good_for_day_orders();
+                ob.prune_good_for_day_orders();
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(100));
+            }
+
+            #[test]
+            fn prune_does_not_affect_trades() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_gfd("2", 100, 50)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+
+                ob.prune_good_for_day_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+            }
+
+            #[test]
+            fn prune_both_sides() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
+                ob.add_order(sell_gfd("3", 110, 50)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 111, 50)).unwrap();
+
+                ob.prune_good_for_day_orders();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(99));
+                assert_eq!(levels.asks.len(), 1);
+                assert_eq!(levels.asks[0].price(), price(111));
+            }
+        }
+
+        mod quote_age {
+            use super::*;
+
+            #[test]
+            fn age_is_none_on_empty_side() {
+                let ob = Orderbook::new();
+                assert!(ob.best_bid_age(Local::now()).is_none());
+                assert!(ob.best_ask_age(Local::now()).is_none());
+            }
+
+            #[test]
+            fn age_reflects_oldest_order_at_touch() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 50)).unwrap();
+                *time.lock().unwrap() += Duration::seconds(10);
+                ob.add_order(buy_order("3".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 110, 50)).unwrap();
+
+                let now = start + Duration::seconds(30);
+                assert_eq!(ob.best_bid_age(now), Some(Duration::seconds(30)));
+                assert_eq!(ob.best_ask_age(now), Some(Duration::seconds(30)));
+            }
+
+            #[test]
+            fn age_resets_when_touch_improves() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                *time.lock().unwrap() += Duration::seconds(10);
+                ob.add_order(buy_order("2".to_string(), 101, 50)).unwrap();
+
+                let now = start + Duration::seconds(30);
+                assert_eq!(ob.best_bid_age(now), Some(Duration::seconds(20)));
+            }
+        }
+
+        mod quantity_in_range {
+            use super::*;
+
+            fn book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 20)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 5)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 97, 40)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 105, 15)).unwrap();
+                ob.add_order(sell_order("6".to_string(), 106, 25)).unwrap();
+                ob.add_order(sell_order("7".to_string(), 110, 35)).unwrap();
+                ob
+            }
+
+            #[test]
+            fn bids_range_spans_several_levels() {
+                let ob = book();
+                assert_eq!(
+                    ob.quantity_in_range(Side::Buy, price(98), price(100)),
+                    qty(35)
+                );
+                assert_eq!(
+                    ob.quantity_in_range(Side::Buy, price(97), price(100)),
+                    qty(75)
+                );
+            }
+
+            #[test]
+            fn asks_range_spans_several_levels() {
+                let ob = book();
+                assert_eq!(
+                    ob.quantity_in_range(Side::Sell, price(105), price(106)),
+                    qty(40)
+                );
+                assert_eq!(
+                    ob.quantity_in_range(Side::Sell, price(100), price(200)),
+                    qty(75)
+                );
+            }
+
+            #[test]
+            fn range_matching_nothing_is_zero() {
+                let ob = book();
+                assert_eq!(
+                    ob.quantity_in_range(Side::Buy, price(101), price(104)),
+                    qty(0)
+                );
+                assert_eq!(
+                    ob.quantity_in_range(Side::Sell, price(107), price(109)),
+                    qty(0)
+                );
+            }
+
+            #[test]
+            fn inverted_range_is_zero() {
+                let ob = book();
+                assert_eq!(
+                    ob.quantity_in_range(Side::Buy, price(100), price(97)),
+                    qty(0)
+                );
+            }
+        }
+
+        mod reserved_price {
+            use super::*;
+
+            #[test]
+            fn limit_at_max_price_rejected() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(buy_order("1".to_string(), u64::MAX, 50));
+                assert!(matches!(result, Err(OrderError::ReservedPrice)));
+                assert!(ob.get_levels().bids.is_empty());
+            }
+
+            #[test]
+            fn limit_at_zero_price_rejected() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(sell_order("1".to_string(), 0, 50));
+                assert!(matches!(result, Err(OrderError::ReservedPrice)));
+                assert!(ob.get_levels().asks.is_empty());
+            }
+
+            #[test]
+            fn market_orders_still_use_sentinels() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                let market = Order::new(
+                    order_id("2"),
+                    OrderType::Market,
+                    Side::Buy,
+                    price(0),
+                    qty(50),
+                );
+                assert!(ob.add_order(market).is_ok());
+            }
+        }
+
+        mod trade_price_policy {
+            use super::*;
+
+            fn cross_with_spread(policy: TradePricePolicy) -> Price {
+                let mut ob = Orderbook::new();
+                ob.set_trade_price_policy(policy);
+                ob.add_order(buy_order("1".to_string(), 110, 50)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+                ob.trades().last().unwrap().bid_trade.price()
+            }
+
+            #[test]
+            fn maker_price_is_default() {
+                assert_eq!(cross_with_spread(TradePricePolicy::default()), price(110));
+            }
+
+            #[test]
+            fn maker_price_uses_resting_ask() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 110, 50)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.trades().last().unwrap().ask_trade.price(), price(100));
+            }
+
+            #[test]
+            fn aggressor_price_uses_incoming_limit() {
+                assert_eq!(
+                    cross_with_spread(TradePricePolicy::AggressorPrice),
+                    price(100)
+                );
+            }
+
+            #[test]
+            fn aggressive_buy_pays_its_own_limit() {
+                for auto_match in [false, true] {
+                    let mut ob = Orderbook::new();
+                    ob.set_trade_price_policy(TradePricePolicy::AggressorPrice);
+                    ob.set_auto_match(auto_match);
+                    ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                    ob.add_order(buy_order("2".to_string(), 110, 50)).unwrap();
+                    ob.match_orders();
+
+                    let trade = ob.trades().last().unwrap();
+                    assert_eq!(trade.price(), price(110));
+                    assert_eq!(trade.aggressor, Side::Buy);
+                }
+            }
+
+            #[test]
+            fn midpoint_splits_the_spread() {
+                assert_eq!(cross_with_spread(TradePricePolicy::Midpoint), price(105));
+            }
+
+            #[test]
+            fn pair_midpoint_splits_crossing_limits() {
+                assert_eq!(
+                    cross_with_spread(TradePricePolicy::PairMidpoint),
+                    price(105)
+                );
+            }
+
+            #[test]
+            fn pair_midpoint_ignores_book_touch() {
+                let mut ob = Orderbook::new();
+                ob.set_trade_price_policy(TradePricePolicy::PairMidpoint);
+                ob.set_auto_match(true);
+                ob.add_order(buy_order("1".to_string(), 90, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 110, 10)).unwrap();
+
+                // The book mid before the trade was 95; the pair mid is 105
+                assert_eq!(ob.trades().last().unwrap().price(), price(105));
+            }
+
+            #[test]
+            fn market_order_trades_at_limit_under_every_policy() {
+                for policy in [
+                    TradePricePolicy::MakerPrice,
+                    TradePricePolicy::Midpoint,
+                    TradePricePolicy::AggressorPrice,
+                    TradePricePolicy::PairMidpoint,
+                ] {
+                    let mut ob = Orderbook::new();
+                    ob.set_trade_price_policy(policy);
+                    ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                    let market = Order::new(
+                        order_id("2"),
+                        OrderType::Market,
+                        Side::Buy,
+                        price(0),
+                        qty(50),
+                    );
+                    ob.add_order(market).unwrap();
+                    ob.match_orders();
+                    assert_eq!(ob.trades().last().unwrap().bid_trade.price(), price(100));
+                }
+            }
+        }
+
+        mod fill_events {
+            use super::*;
+
+            #[test]
+            fn large_aggressor_partially_filled_small_maker_filled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+
+                let events = fill_events(&ob.drain_events());
+                assert_eq!(events.len(), 2);
+                assert!(events.contains(&BookEvent::OrderPartiallyFilled {
+                    order_id: order_id("2"),
+                    filled: qty(20),
+                    remaining: qty(30),
+                }));
+                assert!(events.contains(&BookEvent::OrderFilled {
+                    order_id: order_id("1"),
+                    filled: qty(20),
+                }));
+            }
+
+            #[test]
+            fn remaining_tracks_successive_fills() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 20)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 101, 50)).unwrap();
+                ob.match_orders();
+
+                let aggressor_events: Vec<_> = fill_events(ob.events())
+                    .into_iter()
+                    .filter(|event| event.order_id() == Some(&order_id("3")))
+                    .collect();
+                assert_eq!(
+                    aggressor_events,
+                    vec![
+                        BookEvent::OrderPartiallyFilled {
+                            order_id: order_id("3"),
+                            filled: qty(20),
+                            remaining: qty(30),
+                        },
+                        BookEvent::OrderPartiallyFilled {
+                            order_id: order_id("3"),
+                            filled: qty(20),
+                            remaining: qty(10),
+                        },
+                    ]
+                );
+            }
+
+            #[test]
+            fn drain_empties_event_queue() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+                assert_eq!(fill_events(&ob.drain_events()).len(), 2);
+                assert!(ob.events().is_empty());
+            }
+        }
+
+        mod market_to_limit {
+            use super::*;
+
+            fn buy_market_to_limit(id: &str, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::MarketToLimit,
+                    Side::Buy,
+                    price(0),
+                    qty(q),
+                )
+            }
+
+            #[test]
+            fn rejected_when_no_liquidity() {
+                let mut ob = Orderbook::new();
+                let result = ob.add_order(buy_market_to_limit("1", 50));
+                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+            }
+
+            #[test]
+            fn partial_sweep_rests_at_last_fill_price() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 20)).unwrap();
+                ob.add_order(buy_market_to_limit("3", 50)).unwrap();
+                ob.match_orders();
+
+                let levels = ob.get_levels();
+                assert!(levels.asks.is_empty());
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(101));
+                assert_eq!(levels.bids[0].quantity(), qty(10));
+                assert_eq!(ob.trades().len(), 2);
+            }
+
+            #[test]
+            fn rested_remainder_behaves_as_limit() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(buy_market_to_limit("2", 50)).unwrap();
+                ob.match_orders();
+
+                ob.add_order(sell_order("3".to_string(), 100, 30)).unwrap();
+                ob.match_orders();
+
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+                assert!(levels.asks.is_empty());
+            }
+
+            #[test]
+            fn fully_filled_leaves_nothing_resting() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_market_to_limit("2", 50)).unwrap();
+                ob.match_orders();
+                assert!(ob.get_levels().bids.is_empty());
+            }
+        }
+
+        mod capacity {
+            use super::*;
+
+            #[test]
+            fn empty_book_has_no_levels_or_orders() {
+                let ob = Orderbook::new();
+                assert_eq!(ob.bid_levels(), 0);
+                assert_eq!(ob.ask_levels(), 0);
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn counts_levels_and_orders() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 10)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 105, 10)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 105, 10)).unwrap();
+                ob.add_order(sell_order("6".to_string(), 105, 10)).unwrap();
+
+                assert_eq!(ob.bid_levels(), 2);
+                assert_eq!(ob.ask_levels(), 1);
+                assert_eq!(ob.order_count(), 6);
+            }
+
+            #[test]
+            fn counts_shrink_after_matching() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 10)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.bid_levels(), 0);
+                assert_eq!(ob.ask_levels(), 1);
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod statistics {
+            use super::*;
+
+            #[test]
+            fn empty_session() {
+                let ob = Orderbook::new();
+                let stats = ob.statistics();
+                assert_eq!(stats.total_trades, 0);
+                assert_eq!(stats.total_volume, qty(0));
+                assert!(stats.vwap.is_none());
+                assert!(stats.last.is_none());
+                assert_eq!(stats.open_orders, 0);
+            }
+
+            #[test]
+            fn small_session_matches_hand_computed_values() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 30)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 102, 30)).unwrap();
+                ob.match_orders();
+                ob.add_order(buy_order("4".to_string(), 99, 10)).unwrap();
+                ob.add_order(buy_order("5".to_string(), 98, 10)).unwrap();
+
+                // Trades: 10 @ 100, 20 @ 102; 10 left resting @ 102
+                let stats = ob.statistics();
+                assert_eq!(stats.total_trades, 2);
+                assert_eq!(stats.total_volume, qty(30));
+                assert_eq!(stats.total_notional, 1000 + 2040);
+                assert_eq!(stats.vwap, Some(price(101)));
+                assert_eq!(stats.high, Some(price(102)));
+                assert_eq!(stats.low, Some(price(100)));
+                assert_eq!(stats.last, Some(price(102)));
+                assert_eq!(stats.bid_levels, 2);
+                assert_eq!(stats.ask_levels, 1);
+                assert_eq!(stats.open_orders, 3);
+            }
+        }
+
+        mod participants {
+            use super::*;
+
+            #[test]
+            fn cancel_participant_leaves_others() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10).with_participant(7))
+                    .unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10).with_participant(8))
+                    .unwrap();
+                ob.add_order(sell_order("3".to_string(), 110, 10).with_participant(7))
+                    .unwrap();
+
+                assert_eq!(ob.cancel_participant(7), 2);
+                assert_eq!(ob.order_count(), 1);
+                assert_eq!(ob.get_levels().bids[0].quantity(), qty(10));
+                assert!(ob.get_levels().asks.is_empty());
+                assert!(!ob.is_disconnected(7));
+            }
+
+            #[test]
+            fn disconnect_pulls_orders_and_blocks_adds() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 10).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("3".to_string(), 111, 10).with_participant(8))
+                    .unwrap();
+
+                assert_eq!(ob.disconnect_participant(7), 2);
+                assert!(ob.is_disconnected(7));
+
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+                assert_eq!(levels.asks.len(), 1);
+                assert_eq!(levels.asks[0].price(), price(111));
+
+                let result = ob.add_order(buy_order("4".to_string(), 100, 10).with_participant(7));
+                assert!(matches!(result, Err(OrderError::ParticipantDisconnected)));
+            }
+
+            #[test]
+            fn reconnect_allows_adds_again() {
+                let mut ob = Orderbook::new();
+                ob.disconnect_participant(7);
+                ob.reconnect_participant(7);
+                assert!(!ob.is_disconnected(7));
+                assert!(
+                    ob.add_order(buy_order("1".to_string(), 100, 10).with_participant(7))
+                        .is_ok()
+                );
+            }
+        }
+
+        mod expiry {
+            use super::*;
+
+            #[test]
+            fn expire_due_removes_only_due_orders() {
+                let (_, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.add_order(
+                    buy_order("1".to_string(), 100, 10).with_expiry(start + Duration::minutes(1)),
+                )
+                .unwrap();
+                ob.add_order(
+                    buy_order("2".to_string(), 99, 10).with_expiry(start + Duration::minutes(5)),
+                )
+                .unwrap();
+                ob.add_order(
+                    sell_order("3".to_string(), 110, 10).with_expiry(start + Duration::minutes(2)),
+                )
+                .unwrap();
+                ob.add_order(sell_order("4".to_string(), 111, 10)).unwrap();
+
+                assert_eq!(ob.expire_due(start + Duration::minutes(2)), 2);
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(99));
+                assert_eq!(levels.asks.len(), 1);
+                assert_eq!(levels.asks[0].price(), price(111));
+            }
+
+            #[test]
+            fn match_orders_expires_before_matching() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.add_order(
+                    sell_order("1".to_string(), 100, 10).with_expiry(start + Duration::minutes(1)),
+                )
+                .unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 10)).unwrap();
+
+                *time.lock().unwrap() += Duration::minutes(1);
+                ob.add_order(buy_order("3".to_string(), 101, 10)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("2")
+                );
+                assert!(ob.get_levels().asks.is_empty());
+            }
+        }
+
+        mod shutdown {
+            use super::*;
+
+            #[test]
+            fn shutdown_timeout_without_thread_is_ok() {
+                let mut ob = Orderbook::new();
+                assert!(
+                    ob.shutdown_timeout(std::time::Duration::from_millis(10))
+                        .is_ok()
+                );
+            }
+
+            #[test]
+            fn shutdown_timeout_stops_spawned_prune_thread() {
+                let ob = Arc::new(Mutex::new(Orderbook::new()));
+                Orderbook::start_prune_thread(&ob);
+                assert!(ob.lock().unwrap().prune.handle.is_some());
+
+                let result = ob
+                    .lock()
+                    .unwrap()
+                    .shutdown_timeout(std::time::Duration::from_secs(1));
+                assert!(result.is_ok());
+                assert!(ob.lock().unwrap().prune.handle.is_none());
+            }
+
+            #[test]
+            fn shutdown_timeout_reports_wedged_thread() {
+                let mut ob = Orderbook::new();
+                ob.prune.handle = Some(std::thread::spawn(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }));
+
+                let result = ob.shutdown_timeout(std::time::Duration::from_millis(10));
+                assert!(matches!(result, Err(ShutdownError::Timeout)));
+            }
+
+            #[test]
+            fn dropping_book_stops_prune_thread() {
+                let ob = Arc::new(Mutex::new(Orderbook::new()));
+                Orderbook::start_prune_thread(&ob);
+                // Keep the handle ourselves so dropping the book only signals the thread
+                let handle = ob.lock().unwrap().prune.handle.take().unwrap();
+                drop(ob);
+
+                let deadline = Instant::now() + std::time::Duration::from_secs(1);
+                while !handle.is_finished() && Instant::now() < deadline {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                assert!(handle.is_finished());
+                handle.join().unwrap();
+            }
+        }
+
+        mod poisoned_locks {
+            use super::*;
+
+            fn poison(lock: &Arc<(Mutex<()>, Condvar)>) {
+                let lock = Arc::clone(lock);
+                let _ = std::thread::spawn(move || {
+                    let _guard = lock.0.lock().unwrap();
+                    panic!("poison shutdown lock");
+                })
+                .join();
+            }
+
+            #[test]
+            fn shutdown_completes_with_poisoned_lock() {
+                let mut ob = Orderbook::new();
+                poison(&ob.prune.shutdown_cv);
+                assert!(ob.prune.shutdown_cv.0.is_poisoned());
+
+                ob.shutdown();
+                assert!(ob.prune.shutdown.load(Ordering::Acquire));
+            }
+
+            #[test]
+            fn prune_thread_exits_with_poisoned_lock() {
+                let ob = Arc::new(Mutex::new(Orderbook::new()));
+                let shutdown_cv = Arc::clone(&ob.lock().unwrap().prune.shutdown_cv);
+                poison(&shutdown_cv);
+
+                Orderbook::start_prune_thread(&ob);
+                let result = ob
+                    .lock()
+                    .unwrap()
+                    .shutdown_timeout(std::time::Duration::from_secs(1));
+                assert!(result.is_ok());
+            }
+        }
+
+        mod manual_prune {
+            use super::*;
+
+            fn at(hour: u32, minute: u32) -> DateTime<Local> {
+                Local.with_ymd_and_hms(2024, 1, 2, hour, minute, 0).unwrap()
+            }
+
+            fn book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                let gfd = Order::new(
+                    order_id("1"),
+                    OrderType::GoodForDay,
+                    Side::Buy,
+                    price(100),
+                    qty(10),
+                );
+                ob.add_order(gfd).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 10)).unwrap();
+                ob
+            }
+
+            #[test]
+            fn prune_at_close_cancels_gfd() {
+                let mut ob = book();
+                ob.run_gfd_prune_at(at(16, 0));
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids.len(), 1);
+                assert_eq!(levels.bids[0].price(), price(99));
+            }
+
+            #[test]
+            fn prune_before_close_is_noop() {
+                let mut ob = book();
+                ob.run_gfd_prune_at(at(15, 59));
+                assert_eq!(ob.get_levels().bids.len(), 2);
+            }
+
+            #[test]
+            fn prune_respects_configured_close() {
+                let mut ob = book();
+                ob.set_market_close(NaiveTime::from_hms_opt(12, 30, 0).unwrap());
+                ob.run_gfd_prune_at(at(12, 29));
+                assert_eq!(ob.get_levels().bids.len(), 2);
+                ob.run_gfd_prune_at(at(12, 30));
+                assert_eq!(ob.get_levels().bids.len(), 1);
+            }
+        }
+
+        mod brackets {
+            use super::*;
+
+            fn bracketed_long() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_bracket(buy_order("e".to_string(), 100, 10), price(110), price(95))
+                    .unwrap();
+                ob
+            }
+
+            #[test]
+            fn invalid_bracket_rejected() {
+                let mut ob = Orderbook::new();
+                let result =
+                    ob.add_bracket(buy_order("e".to_string(), 100, 10), price(95), price(110));
+                assert!(matches!(result, Err(OrderError::InvalidBracket)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn children_absent_until_entry_fills() {
+                let mut ob = Orderbook::new();
+                ob.add_bracket(buy_order("e".to_string(), 100, 10), price(110), price(95))
+                    .unwrap();
+                ob.match_orders();
+                assert_eq!(ob.order_count(), 1);
+                assert_eq!(ob.stop_orders().count(), 0);
+            }
+
+            #[test]
+            fn entry_fill_places_children() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+                assert_eq!(levels.asks.len(), 1);
+                assert_eq!(levels.asks[0].price(), price(110));
+                assert_eq!(levels.asks[0].quantity(), qty(10));
+
+                let stops: Vec<_> = ob.stop_orders().collect();
+                assert_eq!(stops.len(), 1);
+                assert_eq!(stops[0].stop_price, price(95));
+                assert_eq!(stops[0].order.side, Side::Sell);
+            }
+
+            #[test]
+            fn take_profit_fill_cancels_stop() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                ob.add_order(buy_order("2".to_string(), 110, 10)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("e-tp")
+                );
+                assert_eq!(ob.stop_orders().count(), 0);
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn stop_trigger_cancels_take_profit() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                ob.add_order(buy_order("2".to_string(), 94, 20)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 94, 5)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.stop_orders().count(), 0);
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("e-sl")
+                );
+
+                let levels = ob.get_levels();
+                assert!(levels.asks.is_empty());
+                assert_eq!(levels.bids[0].quantity(), qty(5));
+            }
+
+            #[test]
+            fn partial_take_profit_fill_shrinks_stop() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                ob.add_order(buy_order("2".to_string(), 110, 3)).unwrap();
+                ob.match_orders();
+
+                let stops: Vec<_> = ob.stop_orders().collect();
+                assert_eq!(stops.len(), 1);
+                assert_eq!(stops[0].order.remaining_quantity, qty(7));
+                assert_eq!(
+                    ob.get_order(order_id("e-tp")).unwrap().remaining_quantity,
+                    qty(7)
+                );
+            }
+
+            #[test]
+            fn rejected_take_profit_still_registers_stop() {
+                let mut ob = bracketed_long();
+                ob.add_order(sell_order("e-tp".to_string(), 120, 1))
+                    .unwrap();
+                ob.match_orders();
+
+                let stops: Vec<_> = ob.stop_orders().collect();
+                assert_eq!(stops.len(), 1);
+                assert_eq!(stops[0].order.order_id, order_id("e-sl"));
+                assert_eq!(ob.get_order(order_id("e-tp")).unwrap().price, price(120));
+            }
+
+            #[test]
+            fn rejected_stop_keeps_take_profit() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                ob.add_order(buy_order("2".to_string(), 94, 5)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 94, 5)).unwrap();
+                ob.match_orders();
+
+                // No bids are left for the stop's market sell
+                assert!(ob.get_order(order_id("e-tp")).is_some());
+                assert_eq!(ob.stop_orders().count(), 1);
+
+                ob.add_order(buy_order("4".to_string(), 94, 20)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 94, 5)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.stop_orders().count(), 0);
+                assert!(ob.get_order(order_id("e-tp")).is_none());
+            }
+
+            #[test]
+            fn rejected_stop_is_reported_once() {
+                let mut ob = bracketed_long();
+                ob.match_orders();
+
+                ob.add_order(buy_order("2".to_string(), 94, 5)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 94, 5)).unwrap();
+                ob.match_orders();
+                ob.match_orders();
+                ob.match_orders();
+
+                let rejections = ob
+                    .events()
+                    .iter()
+                    .filter(|e| matches!(e, BookEvent::OrderRejected { order_id: id, .. } if *id == order_id("e-sl")))
+                    .count();
+                assert_eq!(rejections, 1);
+            }
+
+            #[test]
+            fn cancelled_entry_drops_bracket() {
+                let mut ob = Orderbook::new();
+                ob.add_bracket(buy_order("e".to_string(), 100, 10), price(110), price(95))
+                    .unwrap();
+                ob.cancel_order(order_id("e")).unwrap();
+                assert!(ob.brackets.is_empty());
+            }
+        }
+
+        mod fractional_prices {
+            use super::*;
+
+            #[test]
+            fn midprice_under_fractional_prices() {
+                let mut ob = Orderbook::new();
+                let bid = Price::from_decimal("100.25", 2).unwrap();
+                let ask = Price::from_decimal("100.75", 2).unwrap();
+                ob.add_order(buy_order("1".to_string(), bid.0, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), ask.0, 10))
+                    .unwrap();
+
+                let mid = ob.midprice().unwrap();
+                assert_eq!(mid.display_scaled(2).to_string(), "100.50");
+            }
+
+            #[test]
+            fn levels_sorted_under_fractional_prices() {
+                let mut ob = Orderbook::new();
+                for (id, p) in [("1", "100.25"), ("2", "100.3"), ("3", "99.99")] {
+                    let p = Price::from_decimal(p, 2).unwrap();
+                    ob.add_order(buy_order(id.to_string(), p.0, 10)).unwrap();
+                }
+                let levels = ob.get_levels();
+                assert_eq!(
+                    levels.bids[0].price().display_scaled(2).to_string(),
+                    "100.30"
+                );
+                assert_eq!(
+                    levels.bids[2].price().display_scaled(2).to_string(),
+                    "99.99"
+                );
+            }
+        }
+
+        mod modify_quantity {
+            use super::*;
+
+            fn partially_filled_bid() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 100)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 40)).unwrap();
+                ob.match_orders();
+                ob
+            }
+
+            fn resting(ob: &Orderbook, id: &str) -> Order {
+                ob.bids
+                    .values()
+                    .flat_map(|orders| orders.iter())
+                    .find(|o| o.order_id == order_id(id))
+                    .cloned()
+                    .unwrap()
+            }
+
+            #[test]
+            fn modify_upward_keeps_filled_quantity() {
+                let mut ob = partially_filled_bid();
+                ob.modify_order(order_id("1"), qty(150)).unwrap();
+
+                let order = resting(&ob, "1");
+                assert_eq!(order.remaining_quantity, qty(150));
+                assert_eq!(order.filled_quantity(), qty(40));
+                assert_eq!(order.initial_quantity, qty(190));
+                assert_eq!(ob.get_levels().bids[0].quantity(), qty(150));
+            }
+
+            #[test]
+            fn modify_downward_keeps_filled_quantity() {
+                let mut ob = partially_filled_bid();
+                ob.modify_order(order_id("1"), qty(10)).unwrap();
+
+                let order = resting(&ob, "1");
+                assert_eq!(order.filled_quantity(), qty(40));
+                assert_eq!(order.initial_quantity, qty(50));
+            }
+
+            #[test]
+            fn modify_unknown_order_fails() {
+                let mut ob = Orderbook::new();
+                let result = ob.modify_order(order_id("1"), qty(10));
+                assert!(matches!(result, Err(OrderError::OrderNotFound)));
+            }
+        }
+
+        mod order_status {
+            use super::*;
+
+            #[test]
+            fn status_follows_order_lifecycle() {
+                let mut ob = Orderbook::new();
+                assert_eq!(ob.order_status(order_id("1")), None);
+
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Open));
+
+                ob.add_order(sell_order("2".to_string(), 100, 20)).unwrap();
+                ob.match_orders();
+                assert_eq!(
+                    ob.order_status(order_id("1")),
+                    Some(OrderStatus::PartiallyFilled)
+                );
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Filled));
+
+                ob.cancel_order(order_id("1")).unwrap();
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Cancelled));
+            }
+
+            #[test]
+            fn cancel_filled_order_reports_already_filled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+
+                let result = ob.cancel_order(order_id("1"));
+                assert!(matches!(result, Err(OrderError::AlreadyFilled)));
+                let result = ob.modify_order(order_id("2"), qty(10));
+                assert!(matches!(result, Err(OrderError::AlreadyFilled)));
+            }
+
+            #[test]
+            fn cancel_unknown_order_reports_not_found() {
+                let mut ob = Orderbook::new();
+                let result = ob.cancel_order(order_id("1"));
+                assert!(matches!(result, Err(OrderError::OrderNotFound)));
+            }
+        }
+
+        mod top_orders {
+            use super::*;
+
+            fn ids(orders: Vec<&Order>) -> Vec<OrderId> {
+                orders.into_iter().map(|o| o.order_id.clone()).collect()
+            }
+
+            #[test]
+            fn price_then_fifo_priority() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 98, 10)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 102, 10)).unwrap();
+                ob.add_order(sell_order("6".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("7".to_string(), 101, 10)).unwrap();
+
+                assert_eq!(
+                    ids(ob.top_orders(Side::Buy, 3)),
+                    vec![order_id("2"), order_id("3"), order_id("1")]
+                );
+                assert_eq!(
+                    ids(ob.top_orders(Side::Sell, 10)),
+                    vec![order_id("6"), order_id("7"), order_id("5")]
+                );
+            }
+
+            #[test]
+            fn empty_side_yields_nothing() {
+                let ob = Orderbook::new();
+                assert!(ob.top_orders(Side::Buy, 5).is_empty());
+            }
+        }
+
+        mod queue_position {
+            use super::*;
+
+            #[test]
+            fn queue_ahead_sums_earlier_orders_at_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 30)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 101, 99)).unwrap();
+
+                assert_eq!(ob.queue_ahead(order_id("1")), Some(qty(0)));
+                assert_eq!(ob.queue_ahead(order_id("3")), Some(qty(80)));
+                assert_eq!(ob.queue_ahead(order_id("9")), None);
+            }
+
+            #[test]
+            fn fill_fraction_from_recent_volume() {
+                let mut ob = Orderbook::new();
+                // Print 20 @ 100 and 10 @ 101 on the tape
+                ob.add_order(buy_order("a".to_string(), 100, 20)).unwrap();
+                ob.add_order(sell_order("b".to_string(), 100, 20)).unwrap();
+                ob.match_orders();
+                ob.add_order(buy_order("c".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("d".to_string(), 101, 10)).unwrap();
+                ob.match_orders();
+
+                ob.add_order(buy_order("1".to_string(), 100, 30)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 50)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 10)).unwrap();
+
+                // Only the 20 printed at or below 100 counts against 80 ahead
+                assert_eq!(ob.estimated_fill_fraction(order_id("3")), Some(0.25));
+                assert_eq!(ob.estimated_fill_fraction(order_id("1")), Some(1.0));
+                assert_eq!(ob.estimated_fill_fraction(order_id("9")), None);
+            }
+
+            #[test]
+            fn fill_fraction_caps_at_one() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("a".to_string(), 100, 50)).unwrap();
+                ob.add_order(sell_order("b".to_string(), 100, 50)).unwrap();
+                ob.match_orders();
+
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.estimated_fill_fraction(order_id("2")), Some(1.0));
+            }
+        }
+
+        mod quote {
+            use super::*;
+
+            #[test]
+            fn valid_quote_rests_both_sides() {
+                let mut ob = Orderbook::new();
+                let (bid_id, ask_id) = ob.quote(price(99), price(101), qty(10)).unwrap();
+                assert_ne!(bid_id, ask_id);
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids[0].price(), price(99));
+                assert_eq!(levels.bids[0].quantity(), qty(10));
+                assert_eq!(levels.asks[0].price(), price(101));
+                assert_eq!(levels.asks[0].quantity(), qty(10));
+                assert_eq!(ob.order_status(bid_id), Some(OrderStatus::Open));
+            }
+
+            #[test]
+            fn crossed_quote_rejected() {
+                let mut ob = Orderbook::new();
+                let result = ob.quote(price(101), price(101), qty(10));
+                assert!(matches!(result, Err(OrderError::WouldCross)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn failed_ask_pulls_bid() {
+                let mut ob = Orderbook::new();
+                let result = ob.quote(price(99), Price::max(), qty(10));
+                assert!(matches!(result, Err(OrderError::ReservedPrice)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn generated_ids_skip_existing() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("auto-1".to_string(), 90, 10))
+                    .unwrap();
+                assert_eq!(ob.generate_order_id(), order_id("auto-2"));
+            }
+        }
+
+        mod limited_matching {
+            use super::*;
+
+            fn deep_cross() -> Orderbook {
+                let mut ob = Orderbook::new();
+                for (id, p) in [("1", 100), ("2", 101), ("3", 102), ("4", 103)] {
+                    ob.add_order(sell_order(id.to_string(), p, 10)).unwrap();
+                }
+                ob
+            }
+
+            #[test]
+            fn stops_after_max_trades_and_resumes() {
+                let mut ob = deep_cross();
+                ob.add_order(buy_fak("5".to_string(), 103, 40)).unwrap();
+
+                assert_eq!(ob.match_orders_limited(2), 2);
+                assert_eq!(ob.trades().len(), 2);
+                // Book still crossed, so the FAK hasn't been cleaned up
+                assert_eq!(ob.get_levels().bids[0].quantity(), qty(20));
+
+                assert_eq!(ob.match_orders_limited(2), 2);
+                let levels = ob.get_levels();
+                assert!(levels.bids.is_empty());
+                assert!(levels.asks.is_empty());
+            }
+
+            #[test]
+            fn cleanup_runs_when_drained() {
+                let mut ob = deep_cross();
+                ob.add_order(buy_fak("5".to_string(), 101, 40)).unwrap();
+
+                assert_eq!(ob.match_orders_limited(5), 2);
+                assert!(ob.get_levels().bids.is_empty());
+                assert_eq!(ob.get_levels().asks.len(), 2);
+            }
+
+            #[test]
+            fn zero_budget_produces_nothing() {
+                let mut ob = deep_cross();
+                ob.add_order(buy_order("5".to_string(), 103, 40)).unwrap();
+                assert_eq!(ob.match_orders_limited(0), 0);
+                assert!(ob.trades().is_empty());
+            }
+
+            #[test]
+            fn clearing_empty_front_is_not_counted() {
+                let mut ob = deep_cross();
+                ob.asks
+                    .get_mut(&price(100))
+                    .unwrap()
+                    .front_mut()
+                    .unwrap()
+                    .remaining_quantity = qty(0);
+                ob.add_order(buy_fak("5".to_string(), 101, 10)).unwrap();
+
+                assert_eq!(ob.match_orders_limited(5), 1);
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("2")
+                );
+            }
+        }
+
+        mod simulate {
+            use super::*;
+
+            #[test]
+            fn simulate_reports_trades_without_mutating() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 10)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 101, 15)).unwrap();
+                let before = ob.get_levels();
+
+                let trades = ob.simulate_match();
+                assert_eq!(trades.len(), 2);
+                assert_eq!(trades[0].ask_trade.order_id(), order_id("1"));
+                assert_eq!(trades[0].quantity(), qty(10));
+                assert_eq!(trades[1].ask_trade.order_id(), order_id("2"));
+                assert_eq!(trades[1].quantity(), qty(5));
+
+                assert_eq!(ob.get_levels(), before);
+                assert!(ob.trades().is_empty());
+                assert!(fill_events(ob.events()).is_empty());
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Open));
+            }
+
+            #[test]
+            fn simulate_matches_real_run() {
+                let (clock, _) = mock_clock();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.add_order(buy_order("1".to_string(), 102, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 25)).unwrap();
+
+                let simulated = ob.simulate_match();
+                ob.match_orders();
+                let actual: Vec<Trade> = ob.trades().iter().cloned().collect();
+                assert_eq!(simulated, actual);
+            }
+
+            #[test]
+            fn cloned_book_has_independent_prune_state() {
+                let ob = Arc::new(Mutex::new(Orderbook::new()));
+                Orderbook::start_prune_thread(&ob);
+                let copy = ob.lock().unwrap().clone();
+                assert!(copy.prune.handle.is_none());
+                assert!(!Arc::ptr_eq(
+                    &copy.prune.shutdown,
+                    &ob.lock().unwrap().prune.shutdown
+                ));
+            }
+        }
+
+        mod auto_match {
+            use super::*;
+
+            #[test]
+            fn crossing_limit_fills_on_add() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 4)).unwrap();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(ob.trades().last().unwrap().price(), price(100));
+                assert_eq!(ob.get_levels().asks()[0].quantity(), qty(6));
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Filled));
+            }
+
+            #[test]
+            fn remainder_rests_at_limit() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 8)).unwrap();
+
+                assert!(ob.get_levels().asks().is_empty());
+                let levels = ob.get_levels();
+                let bids = levels.bids();
+                assert_eq!(bids[0].price(), price(101));
+                assert_eq!(bids[0].quantity(), qty(3));
+            }
+
+            #[test]
+            fn crossing_market_order_never_rests() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.drain_events();
+
+                ob.add_order(market_buy("2".to_string(), 8)).unwrap();
+
+                let events = ob.drain_events();
+                assert!(
+                    !events
+                        .iter()
+                        .any(|e| matches!(e, BookEvent::OrderAdded { .. }))
+                );
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+            }
+
+            #[test]
+            fn disabled_by_default() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 5)).unwrap();
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_count(), 2);
+            }
+
+            #[test]
+            fn lifecycle_events_do_not_need_auto_match() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.cancel_order(order_id("1")).unwrap();
+
+                let lifecycle: Vec<_> = ob
+                    .drain_events()
+                    .into_iter()
+                    .filter(|event| event.order_id().is_some())
+                    .collect();
+                assert_eq!(
+                    lifecycle,
+                    vec![
+                        BookEvent::OrderAdded {
+                            order_id: order_id("1"),
+                            side: Side::Buy,
+                            price: price(99),
+                            quantity: qty(5),
+                        },
+                        BookEvent::OrderCancelled {
+                            order_id: order_id("1"),
+                        },
+                    ]
+                );
+            }
+
+            #[test]
+            fn resting_order_emits_added_event() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                assert_eq!(
+                    ob.drain_events(),
+                    vec![
+                        BookEvent::OrderAdded {
+                            order_id: order_id("1"),
+                            side: Side::Buy,
+                            price: price(99),
+                            quantity: qty(5),
+                        },
+                        BookEvent::TouchImproved {
+                            side: Side::Buy,
+                            old: None,
+                            new: price(99),
+                        },
+                    ]
+                );
+            }
+        }
+
+        mod level_priority {
+            use super::*;
+
+            #[test]
+            fn fifo_matches_oldest_first() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("1")
+                );
+            }
+
+            #[test]
+            fn lifo_matches_newest_first() {
+                let mut ob = Orderbook::new();
+                ob.set_level_priority(LevelPriority::Lifo);
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(
+                    ob.trades().last().unwrap().ask_trade.order_id(),
+                    order_id("2")
+                );
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Open));
+            }
+        }
+
+        mod quantity_to_reach {
+            use super::*;
+
+            #[test]
+            fn buy_consumes_asks_below_target() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 7)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 9)).unwrap();
+
+                let needed = ob.quantity_to_reach(Side::Buy, price(102));
+                assert_eq!(needed, qty(12));
+
+                ob.add_order(buy_order("4".to_string(), 101, needed.0))
+                    .unwrap();
+                ob.match_orders();
+                assert_eq!(ob.get_levels().asks()[0].price(), price(102));
+            }
+
+            #[test]
+            fn sell_consumes_bids_above_target() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 7)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 98, 9)).unwrap();
+
+                assert_eq!(ob.quantity_to_reach(Side::Sell, price(99)), qty(5));
+                assert_eq!(ob.quantity_to_reach(Side::Sell, price(100)), qty(0));
+            }
+        }
+
+        mod reset {
+            use super::*;
+
+            #[test]
+            fn reset_empties_busy_book() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 4)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 105, 6)).unwrap();
+                ob.add_bracket(buy_order("4".to_string(), 90, 5), price(95), price(85))
+                    .unwrap();
+                ob.generate_order_id();
+                ob.match_orders();
+                assert!(!ob.trades().is_empty());
+
+                ob.reset();
+
+                assert!(ob.get_levels().bids().is_empty());
+                assert!(ob.get_levels().asks().is_empty());
+                assert_eq!(ob.order_count(), 0);
+                assert!(ob.trades().is_empty());
+                assert!(ob.events().is_empty());
+                assert_eq!(ob.stop_orders().count(), 0);
+                assert_eq!(ob.order_status(order_id("1")), None);
+                assert_eq!(ob.next_sequence, 0);
+                assert_eq!(ob.generate_order_id(), order_id("auto-1"));
+            }
+
+            #[test]
+            fn reset_keeps_configuration() {
+                let mut ob = Orderbook::new();
+                ob.set_level_priority(LevelPriority::Lifo);
+                ob.reset();
+                assert_eq!(ob.level_priority, LevelPriority::Lifo);
+
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod duplicate_guard {
+            use super::*;
+
+            #[test]
+            fn duplicate_caught_when_only_in_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.orders.delete(order_id("1"));
+
+                let result = ob.add_order(buy_order("1".to_string(), 101, 10));
+                assert!(matches!(result, Err(OrderError::IdExists)));
+                assert_eq!(ob.bid_levels(), 1);
+            }
+
+            #[test]
+            fn duplicate_caught_when_only_in_index() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.remove_from_level(Side::Sell, price(100), order_id("1"));
+
+                let result = ob.add_order(sell_order("1".to_string(), 99, 10));
+                assert!(matches!(result, Err(OrderError::IdExists)));
+                assert_eq!(ob.ask_levels(), 0);
+            }
+        }
+
+        mod improve_order {
+            use super::*;
+
+            #[test]
+            fn improves_bid_by_ticks() {
+                let mut ob = Orderbook::new();
+                ob.set_tick_size(price(5));
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 120, 10)).unwrap();
+
+                ob.improve_order(order_id("1"), 2).unwrap();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[0].price(), price(110));
+                assert_eq!(levels.bids()[0].quantity(), qty(10));
+                assert_eq!(ob.bid_levels(), 1);
+            }
+
+            #[test]
+            fn improves_ask_down() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 105, 10)).unwrap();
+                ob.improve_order(order_id("1"), 3).unwrap();
+                assert_eq!(ob.get_levels().asks()[0].price(), price(102));
+            }
+
+            #[test]
+            fn rejects_improvement_that_would_cross() {
+                let mut ob = Orderbook::new();
+                ob.set_tick_size(price(5));
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 10)).unwrap();
+
+                let result = ob.improve_order(order_id("1"), 2);
+                assert!(matches!(result, Err(OrderError::WouldCross)));
+                assert_eq!(ob.get_levels().bids()[0].price(), price(100));
+            }
+
+            #[test]
+            fn rejects_filled_order() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                ob.match_orders();
+
+                let result = ob.improve_order(order_id("1"), 1);
+                assert!(matches!(result, Err(OrderError::AlreadyFilled)));
+            }
+
+            #[test]
+            fn reprice_moves_order_to_back_of_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10)).unwrap();
+                ob.modify_order_price(order_id("1"), price(100)).unwrap();
+
+                ob.add_order(sell_order("3".to_string(), 100, 10)).unwrap();
+                ob.match_orders();
+                assert_eq!(
+                    ob.trades().last().unwrap().bid_trade.order_id(),
+                    order_id("2")
+                );
+            }
+        }
+
+        mod touch_events {
+            use super::*;
+
+            fn touch_events(ob: &mut Orderbook) -> Vec<BookEvent> {
+                ob.drain_events()
+                    .into_iter()
+                    .filter(|e| matches!(e, BookEvent::TouchImproved { .. }))
+                    .collect()
+            }
+
+            #[test]
+            fn one_event_per_bid_improvement() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 10)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 10)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 102, 10)).unwrap();
+
+                assert_eq!(
+                    touch_events(&mut ob),
+                    vec![
+                        BookEvent::TouchImproved {
+                            side: Side::Buy,
+                            old: None,
+                            new: price(100),
+                        },
+                        BookEvent::TouchImproved {
+                            side: Side::Buy,
+                            old: Some(price(100)),
+                            new: price(101),
+                        },
+                        BookEvent::TouchImproved {
+                            side: Side::Buy,
+                            old: Some(price(101)),
+                            new: price(102),
+                        },
+                    ]
+                );
+            }
+
+            #[test]
+            fn cancel_and_match_report_touch_changes() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 5)).unwrap();
+                ob.drain_events();
+
+                ob.cancel_order(order_id("1")).unwrap();
+                assert_eq!(
+                    touch_events(&mut ob),
+                    vec![BookEvent::TouchImproved {
+                        side: Side::Sell,
+                        old: Some(price(100)),
+                        new: price(101),
+                    }]
+                );
+
+                ob.add_order(buy_order("4".to_string(), 101, 5)).unwrap();
+                ob.match_orders();
+                let events = touch_events(&mut ob);
+                assert_eq!(
+                    events.last(),
+                    Some(&BookEvent::TouchImproved {
+                        side: Side::Sell,
+                        old: Some(price(101)),
+                        new: price(102),
+                    })
+                );
+            }
+        }
+
+        mod max_order_quantity {
+            use super::*;
+
+            #[test]
+            fn accepts_order_at_limit() {
+                let mut ob = Orderbook::new();
+                ob.set_max_order_quantity(Some(qty(100)));
+                ob.add_order(buy_order("1".to_string(), 100, 100)).unwrap();
+                assert_eq!(ob.order_count(), 1);
+            }
+
+            #[test]
+            fn rejects_order_above_limit() {
+                let mut ob = Orderbook::new();
+                ob.set_max_order_quantity(Some(qty(100)));
+                let result = ob.add_order(sell_order("1".to_string(), 100, 101));
+                assert!(matches!(result, Err(OrderError::OrderTooLarge)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn applies_to_market_orders() {
+                let mut ob = Orderbook::new();
+                ob.set_max_order_quantity(Some(qty(10)));
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                let market = market_buy("2".to_string(), 11);
+                assert!(matches!(
+                    ob.add_order(market),
+                    Err(OrderError::OrderTooLarge)
+                ));
+            }
+
+            #[test]
+            fn rejects_modify_above_limit() {
+                let mut ob = Orderbook::new();
+                ob.set_max_order_quantity(Some(qty(10)));
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                assert!(matches!(
+                    ob.modify_order(order_id("1"), qty(7)),
+                    Err(OrderError::OrderTooLarge)
+                ));
+                assert_eq!(ob.modification_count(order_id("1")), Some(0));
+                ob.modify_order(order_id("1"), qty(6)).unwrap();
+                assert_eq!(
+                    ob.get_order(order_id("1")).unwrap().remaining_quantity,
+                    qty(6)
+                );
+            }
+        }
+
+        mod l3_snapshot {
+            use super::*;
+
+            #[test]
+            fn partial_fill_reports_progress() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                let asks = ob.l3_snapshot(Side::Sell);
+                assert_eq!(
+                    asks,
+                    vec![
+                        OrderSnapshot {
+                            order_id: order_id("1"),
+                            side: Side::Sell,
+                            price: price(100),
+                            initial_quantity: qty(10),
+                            filled_quantity: qty(4),
+                            remaining_quantity: qty(6),
+                        },
+                        OrderSnapshot {
+                            order_id: order_id("2"),
+                            side: Side::Sell,
+                            price: price(101),
+                            initial_quantity: qty(5),
+                            filled_quantity: qty(0),
+                            remaining_quantity: qty(5),
+                        },
+                    ]
+                );
+                assert!(ob.l3_snapshot(Side::Buy).is_empty());
+            }
+        }
+
+        mod reduce_only {
+            use super::*;
+
+            #[test]
+            fn reduce_only_sell_capped_at_long_position() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5).with_participant(7))
+                    .unwrap();
+                ob.match_orders();
+
+                ob.add_order(buy_order("3".to_string(), 99, 20)).unwrap();
+                ob.add_order(
+                    sell_order("4".to_string(), 99, 8)
+                        .with_participant(7)
+                        .with_reduce_only(),
+                )
+                .unwrap();
+                ob.match_orders();
+
+                let last = ob.trades().last().unwrap();
+                assert_eq!(last.ask_trade.order_id(), order_id("4"));
+                assert_eq!(last.quantity(), qty(5));
+                assert_eq!(ob.order_status(order_id("4")), Some(OrderStatus::Filled));
+                assert!(ob.get_levels().asks().is_empty());
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(15));
+            }
+
+            #[test]
+            fn reduce_only_without_position_is_cancelled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(
+                    sell_order("2".to_string(), 100, 5)
+                        .with_participant(3)
+                        .with_reduce_only(),
+                )
+                .unwrap();
+                ob.match_orders();
+
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(10));
+            }
+        }
+
+        mod positions {
+            use super::*;
+
+            #[test]
+            fn buy_then_sell_nets_out() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10).with_participant(1))
+                    .unwrap();
+                ob.match_orders();
+                assert_eq!(ob.position(1), 10);
+
+                ob.add_order(buy_order("3".to_string(), 101, 4)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 4).with_participant(1))
+                    .unwrap();
+                ob.match_orders();
+                assert_eq!(ob.position(1), 6);
+            }
+
+            #[test]
+            fn unknown_participant_is_flat() {
+                let ob = Orderbook::new();
+                assert_eq!(ob.position(42), 0);
+            }
+
+            #[test]
+            fn short_position_is_negative() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 3).with_participant(9))
+                    .unwrap();
+                ob.match_orders();
+                assert_eq!(ob.position(9), -3);
+            }
+        }
+
+        mod weighted_mid {
+            use super::*;
+
+            #[test]
+            fn none_when_a_side_is_empty() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.weighted_mid(3), None);
+            }
+
+            #[test]
+            fn leans_toward_heavier_side() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 30)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 110, 10)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 111, 10)).unwrap();
+
+                let mid = ob.midprice().unwrap();
+                let weighted = ob.weighted_mid(2).unwrap();
+                assert!(weighted < mid);
+                // (3000 + 4950 + 1100 + 1110) / 100
+                assert_eq!(weighted, price(101));
+            }
+
+            #[test]
+            fn depth_limits_levels_used() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 50, 1000)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 110, 10)).unwrap();
+                assert_eq!(ob.weighted_mid(1), Some(price(105)));
+            }
+        }
+
+        mod cancel_older_than {
+            use super::*;
+
+            #[test]
+            fn cancels_only_stale_orders() {
+                let (clock, time) = mock_clock();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 10)).unwrap();
+                *time.lock().unwrap() += Duration::seconds(60);
+                ob.add_order(buy_order("3".to_string(), 99, 10)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 111, 10)).unwrap();
+                *time.lock().unwrap() += Duration::seconds(10);
+
+                let now = *time.lock().unwrap();
+                let cancelled = ob.cancel_orders_older_than(now, Duration::seconds(30));
+
+                assert_eq!(cancelled, 2);
+                assert_eq!(ob.order_count(), 2);
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_status(order_id("3")), Some(OrderStatus::Open));
+            }
+        }
+
+        mod match_incoming {
+            use super::*;
+
+            #[test]
+            fn matches_only_the_target_order() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 100, 8)).unwrap();
+
+                let trades = ob.match_incoming(order_id("4"));
+
+                assert_eq!(trades.len(), 1);
+                assert_eq!(trades[0].bid_trade.order_id(), order_id("4"));
+                assert_eq!(trades[0].ask_trade.order_id(), order_id("1"));
+                assert_eq!(trades[0].quantity(), qty(5));
+                // The crossed pair at 101 is still waiting for the global matcher
+                assert_eq!(ob.order_status(order_id("3")), Some(OrderStatus::Open));
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Open));
+                assert_eq!(
+                    ob.order_status(order_id("4")),
+                    Some(OrderStatus::PartiallyFilled)
+                );
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[1].price(), price(100));
+                assert_eq!(levels.bids()[1].quantity(), qty(3));
+            }
+
+            #[test]
+            fn unknown_order_produces_no_trades() {
+                let mut ob = Orderbook::new();
+                assert!(ob.match_incoming(order_id("missing")).is_empty());
+            }
+
+            #[test]
+            fn remainder_keeps_queue_position() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 99, 2)).unwrap();
+                ob.match_incoming(order_id("1"));
+
+                let front = ob.top_orders(Side::Buy, 1);
+                assert_eq!(front[0].order_id, order_id("1"));
+                assert_eq!(front[0].remaining_quantity, qty(3));
+            }
+        }
+
+        mod iceberg {
+            use super::*;
+
+            fn display_sizes(seed: u64) -> Vec<u64> {
+                let mut ob = Orderbook::new();
+                ob.set_rng_seed(seed);
+                ob.add_order(sell_order("ice".to_string(), 100, 20).with_iceberg(qty(2), qty(6)))
+                    .unwrap();
+
+                let mut sizes = Vec::new();
+                let mut n = 0;
+                while let Some(level) = ob.get_levels().asks().first().copied() {
+                    sizes.push(level.quantity().0);
+                    n += 1;
+                    ob.add_order(buy_order(format!("b{n}"), 100, level.quantity().0))
+                        .unwrap();
+                    ob.match_orders();
+                }
+                sizes
+            }
+
+            #[test]
+            fn seeded_refills_are_reproducible() {
+                let sizes = display_sizes(1);
+                assert_eq!(sizes, vec![3, 2, 4, 2, 5, 4]);
+                assert_eq!(sizes.iter().sum::<u64>(), 20);
+                assert_eq!(display_sizes(1), sizes);
+            }
+
+            #[test]
+            fn display_stays_within_range() {
+                for seed in 1..20 {
+                    let sizes = display_sizes(seed);
+                    let (last, rest) = sizes.split_last().unwrap();
+                    assert!(rest.iter().all(|s| (2..=6).contains(s)));
+                    assert!(*last <= 6);
+                    assert_eq!(sizes.iter().sum::<u64>(), 20);
+                }
+            }
+
+            #[test]
+            fn iceberg_reports_total_leaves_in_events() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("ice".to_string(), 100, 10).with_iceberg(qty(4), qty(4)))
+                    .unwrap();
+                ob.add_order(buy_order("1".to_string(), 100, 4)).unwrap();
+                ob.drain_events();
+                ob.match_orders();
+
+                let events = ob.drain_events();
+                assert!(events.contains(&BookEvent::OrderPartiallyFilled {
+                    order_id: order_id("ice"),
+                    filled: qty(4),
+                    remaining: qty(6),
+                }));
+                assert_eq!(
+                    ob.order_status(order_id("ice")),
+                    Some(OrderStatus::PartiallyFilled)
+                );
+                assert_eq!(ob.get_levels().asks()[0].quantity(), qty(4));
+            }
+
+            #[test]
+            fn refill_loses_time_priority() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("ice".to_string(), 100, 10).with_iceberg(qty(3), qty(3)))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                let asks = ob.l3_snapshot(Side::Sell);
+                assert_eq!(asks[0].order_id, order_id("2"));
+                assert_eq!(asks[0].remaining_quantity, qty(4));
+                assert_eq!(asks[1].order_id, order_id("ice"));
+                assert_eq!(asks[1].filled_quantity, qty(3));
+            }
+        }
+
+        mod shrink_to_fit {
+            use super::*;
+
+            #[test]
+            fn book_works_after_churn_and_shrink() {
+                let mut ob = Orderbook::new();
+                for i in 0..500 {
+                    ob.add_order(buy_order(format!("b{i}"), 100 - (i % 5), 10))
+                        .unwrap();
+                }
+                for i in 0..500 {
+                    ob.cancel_order(order_id(&format!("b{i}"))).unwrap();
+                }
+                ob.add_order(buy_order("keep".to_string(), 100, 10))
+                    .unwrap();
+                ob.bids.entry(Reverse(price(90))).or_default();
+
+                ob.shrink_to_fit();
+
+                assert!(ob.bids.values().all(|orders| !orders.is_empty()));
+                assert_eq!(ob.bid_levels(), 1);
+                ob.add_order(sell_order("s".to_string(), 100, 10)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(ob.order_count(), 0);
+            }
+        }
+
+        mod book_phase {
+            use super::*;
+
+            #[test]
+            fn continuous_by_default() {
+                assert_eq!(Orderbook::new().phase(), BookPhase::Continuous);
+            }
+
+            #[test]
+            fn pre_open_queues_without_matching() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 102, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+
+                assert_eq!(ob.match_orders_limited(usize::MAX), 0);
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_count(), 2);
+            }
+
+            #[test]
+            fn pre_open_blocks_incoming_match() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 10)).unwrap();
+
+                assert!(ob.match_incoming(order_id("2")).is_empty());
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_count(), 2);
+            }
+
+            #[test]
+            fn closed_rejects_new_orders() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::Closed);
+                let result = ob.add_order(buy_order("1".to_string(), 100, 10));
+                assert!(matches!(result, Err(OrderError::MarketClosed)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn uncross_executes_at_single_price() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 103, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 99, 5)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 104, 10)).unwrap();
+
+                let result = ob.auction_uncross();
+
+                assert_eq!(result, Some((price(101), qty(15))));
+                assert_eq!(ob.phase(), BookPhase::Continuous);
+                assert_eq!(ob.trades().total_volume(), qty(15));
+                assert!(ob.trades().iter().all(|t| t.price() == price(101)));
+                assert_eq!(ob.get_levels().bids()[0].price(), price(101));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(5));
+                assert_eq!(ob.get_levels().asks()[0].price(), price(104));
+            }
+
+            #[test]
+            fn uncross_without_cross_only_opens() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 99, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+
+                assert_eq!(ob.auction_uncross(), None);
+                assert_eq!(ob.phase(), BookPhase::Continuous);
+                assert!(ob.trades().is_empty());
+            }
+
+            #[test]
+            fn uncross_leaves_closed_book_closed() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                ob.set_phase(BookPhase::Closed);
+
+                assert_eq!(ob.auction_uncross(), None);
+                assert_eq!(ob.phase(), BookPhase::Closed);
+                assert!(ob.trades().is_empty());
+            }
+        }
+
+        mod rejection_events {
+            use super::*;
+
+            #[test]
+            fn duplicate_id_emits_rejection() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.drain_events();
+
+                let result = ob.add_order(sell_order("1".to_string(), 105, 10));
+
+                assert_eq!(result, Err(OrderError::IdExists));
+                assert_eq!(
+                    ob.drain_events(),
+                    vec![BookEvent::OrderRejected {
+                        order_id: order_id("1"),
+                        reason: OrderError::IdExists,
+                    }]
+                );
+            }
+
+            #[test]
+            fn accepted_order_emits_no_rejection() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                assert!(
+                    !ob.events()
+                        .iter()
+                        .any(|e| matches!(e, BookEvent::OrderRejected { .. }))
+                );
+            }
+        }
+
+        mod from_levels {
+            use super::*;
+
+            #[test]
+            fn builds_three_by_three_book() {
+                let bids = [
+                    (price(100), qty(10)),
+                    (price(99), qty(20)),
+                    (price(98), qty(30)),
+                ];
+                let asks = [
+                    (price(101), qty(5)),
+                    (price(102), qty(15)),
+                    (price(103), qty(25)),
+                ];
+                let ob = Orderbook::from_levels(&bids, &asks).unwrap();
+
+                let levels = ob.get_levels();
+                let to_pairs = |infos: &[LevelInfo]| -> Vec<(Price, Quantity)> {
+                    infos.iter().map(|l| (l.price(), l.quantity())).collect()
+                };
+                assert_eq!(to_pairs(levels.bids()), bids.to_vec());
+                assert_eq!(to_pairs(levels.asks()), asks.to_vec());
+                assert_eq!(ob.order_count(), 6);
+            }
+
+            #[test]
+            fn rejects_crossed_levels() {
+                let result =
+                    Orderbook::from_levels(&[(price(101), qty(1))], &[(price(100), qty(1))]);
+                assert!(matches!(result, Err(OrderError::WouldCross)));
+            }
+        }
+
+        mod reset_keep_trades {
+            use super::*;
+
+            #[test]
+            fn trades_survive_while_book_empties() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 4)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 105, 6)).unwrap();
+                ob.match_orders();
+                let tape: Vec<Trade> = ob.trades().iter().cloned().collect();
+
+                ob.reset_book_keep_trades();
+
+                assert_eq!(ob.order_count(), 0);
+                assert!(ob.get_levels().bids().is_empty());
+                assert!(ob.get_levels().asks().is_empty());
+                assert_eq!(ob.trades().iter().cloned().collect::<Vec<_>>(), tape);
+            }
+
+            #[test]
+            fn new_trades_continue_the_tape() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 1)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 1)).unwrap();
+                ob.match_orders();
+                ob.reset_book_keep_trades();
+
+                ob.add_order(buy_order("3".to_string(), 100, 1)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 100, 1)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.trades().len(), 2);
+                assert_eq!(ob.trades().last().unwrap().sequence, 2);
+            }
+        }
+
+        mod cancel_where {
+            use super::*;
+
+            fn busy_book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 5)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 102, 50)).unwrap();
+                ob.add_order(Order::new(
+                    order_id("5"),
+                    OrderType::GoodForDay,
+                    Side::Sell,
+                    price(103),
+                    qty(7),
+                ))
+                .unwrap();
+                ob
+            }
+
+            #[test]
+            fn cancels_by_side() {
+                let mut ob = busy_book();
+                assert_eq!(ob.cancel_where(|o| o.side == Side::Buy), 2);
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.ask_levels(), 3);
+            }
+
+            #[test]
+            fn cancels_by_order_type() {
+                let mut ob = busy_book();
+                assert_eq!(
+                    ob.cancel_where(|o| o.order_type == OrderType::GoodForDay),
+                    1
+                );
+                assert_eq!(ob.order_status(order_id("5")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_count(), 4);
+            }
+
+            #[test]
+            fn cancels_by_quantity_threshold() {
+                let mut ob = busy_book();
+                assert_eq!(ob.cancel_where(|o| o.remaining_quantity >= qty(10)), 2);
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_status(order_id("4")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_count(), 3);
+            }
+
+            #[test]
+            fn predicate_sees_partial_fills() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 15)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.cancel_where(|o| o.remaining_quantity < qty(10)), 1);
+            }
+        }
+
+        mod matchable_volume {
+            use super::*;
+
+            #[test]
+            fn volume_at_candidate_prices() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 103, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 99, 5)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 10)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 104, 10)).unwrap();
+
+                assert_eq!(ob.matchable_volume_at(price(98)), qty(0));
+                assert_eq!(ob.matchable_volume_at(price(99)), qty(5));
+                assert_eq!(ob.matchable_volume_at(price(101)), qty(15));
+                assert_eq!(ob.matchable_volume_at(price(103)), qty(10));
+                assert_eq!(ob.matchable_volume_at(price(104)), qty(0));
+            }
+        }
+
+        mod indicative_uncross {
+            use super::*;
+
+            #[test]
+            fn indicative_matches_actual_uncross() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 105, 8)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 102, 12)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 100, 6)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 102, 9)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 106, 4)).unwrap();
+
+                let before = ob.get_levels();
+                let indicative = ob.indicative_uncross();
+                assert_eq!(ob.get_levels(), before);
+                assert!(ob.trades().is_empty());
+
+                assert_eq!(indicative, Some((price(102), qty(15))));
+                assert_eq!(ob.auction_uncross(), indicative);
+                assert_eq!(ob.trades().total_volume(), qty(15));
+            }
+
+            #[test]
+            fn tie_prefers_price_nearest_last_trade() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("a".to_string(), 120, 1)).unwrap();
+                ob.add_order(sell_order("b".to_string(), 120, 1)).unwrap();
+                ob.match_orders();
+
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 110, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.indicative_uncross(), Some((price(110), qty(10))));
+            }
+
+            #[test]
+            fn none_when_nothing_crosses() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.indicative_uncross(), None);
+            }
+        }
+
+        mod total_filled_quantity {
+            use super::*;
+
+            #[test]
+            fn counter_survives_clear_trades() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 10)).unwrap();
+                ob.match_orders();
+                ob.clear_trades();
+
+                ob.add_order(buy_order("3".to_string(), 100, 7)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().total_volume(), qty(5));
+                assert_eq!(ob.total_filled_quantity(), 15);
+            }
+
+            #[test]
+            fn counts_auto_matched_fills() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 4)).unwrap();
+                assert_eq!(ob.total_filled_quantity(), 4);
+            }
+        }
+
+        mod inverted_priority {
+            use super::*;
+
+            fn inverted_book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.set_price_priority(PricePriority::Inverted);
+                ob
+            }
+
+            #[test]
+            fn best_prices_flip() {
+                let mut ob = inverted_book();
+                ob.add_order(buy_order("1".to_string(), 105, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 103, 10)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 10)).unwrap();
+                ob.match_orders();
+
+                // Bids at 103/105 and asks at 100/101 don't cross when lower is better
+                assert!(ob.trades().is_empty());
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[0].price(), price(103));
+                assert_eq!(levels.bids()[1].price(), price(105));
+                assert_eq!(levels.asks()[0].price(), price(101));
+                assert_eq!(levels.asks()[1].price(), price(100));
+            }
+
+            #[test]
+            fn matches_when_bid_at_or_below_ask() {
+                let mut ob = inverted_book();
+                ob.add_order(sell_order("1".to_string(), 102, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 104, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 102, 8)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 2);
+                let first = ob.trades().iter().next().unwrap();
+                assert_eq!(first.ask_trade.order_id(), order_id("2"));
+                assert_eq!(first.price(), price(104));
+                assert_eq!(ob.trades().last().unwrap().price(), price(102));
+                assert_eq!(ob.get_levels().asks()[0].quantity(), qty(2));
+            }
+
+            #[test]
+            fn market_order_sweeps_best_first() {
+                let mut ob = inverted_book();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(market_buy("3".to_string(), 5)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(ob.trades().last().unwrap().price(), price(101));
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod orders_at {
+            use super::*;
+
+            #[test]
+            fn queue_order_matches_insertion() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("a".to_string(), 100, 1)).unwrap();
+                ob.add_order(sell_order("b".to_string(), 101, 1)).unwrap();
+                ob.add_order(sell_order("c".to_string(), 100, 2)).unwrap();
+                ob.add_order(sell_order("d".to_string(), 100, 3)).unwrap();
+
+                let ids: Vec<OrderId> = ob
+                    .orders_at(Side::Sell, price(100))
+                    .unwrap()
+                    .iter()
+                    .map(|o| o.order_id.clone())
+                    .collect();
+                assert_eq!(ids, vec![order_id("a"), order_id("c"), order_id("d")]);
+            }
+
+            #[test]
+            fn none_for_empty_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 1)).unwrap();
+                assert!(ob.orders_at(Side::Buy, price(99)).is_none());
+                assert!(ob.orders_at(Side::Sell, price(100)).is_none());
+            }
+        }
+
+        mod good_for_duration {
+            use super::*;
+
+            fn gfd_order(id: &str, ttl: Duration) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::GoodForDuration(ttl),
+                    Side::Buy,
+                    price(100),
+                    qty(10),
+                )
+            }
+
+            #[test]
+            fn expires_after_relative_duration() {
+                let (clock, time) = mock_clock();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.add_order(gfd_order("1", Duration::minutes(5))).unwrap();
+
+                *time.lock().unwrap() += Duration::minutes(4);
+                ob.match_orders();
+                assert_eq!(ob.order_count(), 1);
+
+                *time.lock().unwrap() += Duration::minutes(1);
+                ob.match_orders();
+                assert_eq!(ob.order_count(), 0);
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Cancelled));
+            }
+
+            #[test]
+            fn earlier_explicit_expiry_wins() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.add_order(
+                    gfd_order("1", Duration::minutes(5)).with_expiry(start + Duration::minutes(1)),
+                )
+                .unwrap();
+
+                assert_eq!(ob.expire_due(start + Duration::minutes(1)), 1);
+            }
+        }
+
+        mod side_emptied {
+            use super::*;
+
+            fn emptied(events: &[BookEvent]) -> Vec<Side> {
+                events
+                    .iter()
+                    .filter_map(|e| match e {
+                        BookEvent::SideEmptied { side } => Some(*side),
+                        _ => None,
+                    })
+                    .collect()
+            }
+
+            #[test]
+            fn fires_once_when_matching_drains_a_side() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 101, 20)).unwrap();
+                ob.match_orders();
+                ob.match_orders();
+
+                assert_eq!(emptied(&ob.drain_events()), vec![Side::Sell]);
+                assert_eq!(ob.bid_levels(), 1);
+            }
+
+            #[test]
+            fn fires_when_last_order_cancelled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 5)).unwrap();
+                ob.cancel_order(order_id("1")).unwrap();
+                assert!(emptied(&ob.drain_events()).is_empty());
+
+                ob.cancel_order(order_id("2")).unwrap();
+                assert_eq!(emptied(&ob.drain_events()), vec![Side::Buy]);
+            }
+        }
+
+        mod fills_for {
+            use super::*;
+
+            #[test]
+            fn fills_are_in_sequence_order() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 4)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 5)).unwrap();
+                ob.add_order(buy_order("x".to_string(), 99, 1)).unwrap();
+                ob.add_order(sell_order("y".to_string(), 99, 1)).unwrap();
+                ob.match_orders();
+                ob.add_order(buy_order("4".to_string(), 102, 12)).unwrap();
+                ob.match_orders();
+
+                let fills = ob.fills_for(order_id("4"));
+                assert_eq!(
+                    fills,
+                    vec![
+                        (2, price(100), qty(3)),
+                        (3, price(101), qty(4)),
+                        (4, price(102), qty(5)),
+                    ]
+                );
+                assert!(fills.windows(2).all(|w| w[0].0 < w[1].0));
+                assert!(ob.fills_for(order_id("missing")).is_empty());
+            }
+        }
+
+        mod modify_under_auto_match {
+            use super::*;
+
+            #[test]
+            fn repriced_bid_into_ask_trades_immediately() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3)).unwrap();
+                assert!(ob.trades().is_empty());
+
+                ob.modify_order_price(order_id("2"), price(101)).unwrap();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(ob.trades().last().unwrap().price(), price(101));
+                assert_eq!(ob.trades().last().unwrap().quantity(), qty(3));
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.get_levels().asks()[0].quantity(), qty(2));
+            }
+
+            #[test]
+            fn repriced_bid_waits_without_auto_match() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 101, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3)).unwrap();
+
+                ob.modify_order_price(order_id("2"), price(101)).unwrap();
+
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(3));
+            }
+
+            #[test]
+            fn resized_crossed_order_matches_when_auto_match_enabled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3)).unwrap();
+                ob.set_auto_match(true);
+
+                ob.modify_order(order_id("2"), qty(4)).unwrap();
+
+                assert_eq!(ob.trades().last().unwrap().quantity(), qty(4));
+                assert_eq!(ob.get_levels().asks()[0].quantity(), qty(1));
+            }
+        }
+
+        mod level_iterators {
+            use super::*;
+
+            #[test]
+            fn both_sides_yield_best_first_with_queues() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 98, 1)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 2)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 3)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 102, 4)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 101, 5)).unwrap();
+
+                let bids: Vec<(Price, usize)> = ob
+                    .bid_levels_iter()
+                    .map(|(p, orders)| (p, orders.len()))
+                    .collect();
+                assert_eq!(bids, vec![(price(99), 2), (price(98), 1)]);
+
+                let (best_bid, queue) = ob.bid_levels_iter().next().unwrap();
+                assert_eq!(best_bid, price(99));
+                let ids: Vec<OrderId> = queue.iter().map(|o| o.order_id.clone()).collect();
+                assert_eq!(ids, vec![order_id("2"), order_id("3")]);
+
+                let asks: Vec<Price> = ob.ask_levels_iter().map(|(p, _)| p).collect();
+                assert_eq!(asks, vec![price(101), price(102)]);
+            }
+
+            #[test]
+            fn inverted_priority_flips_order() {
+                let mut ob = Orderbook::new();
+                ob.set_price_priority(PricePriority::Inverted);
+                ob.add_order(buy_order("1".to_string(), 98, 1)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 1)).unwrap();
+
+                let bids: Vec<Price> = ob.bid_levels_iter().map(|(p, _)| p).collect();
+                assert_eq!(bids, vec![price(98), price(99)]);
+            }
+        }
+
+        mod replace_preserving_priority {
+            use super::*;
+
+            #[test]
+            fn amend_down_keeps_queue_position() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+
+                ob.replace_order_preserve_priority(buy_order("1".to_string(), 100, 4))
+                    .unwrap();
+
+                let queue = ob.orders_at(Side::Buy, price(100)).unwrap();
+                assert_eq!(queue.front().unwrap().order_id, order_id("1"));
+                assert_eq!(queue.front().unwrap().remaining_quantity, qty(4));
+                assert_eq!(ob.queue_ahead(order_id("2")), Some(qty(4)));
+            }
+
+            #[test]
+            fn amend_up_loses_queue_position() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+
+                ob.replace_order_preserve_priority(buy_order("1".to_string(), 100, 12))
+                    .unwrap();
+
+                let queue = ob.orders_at(Side::Buy, price(100)).unwrap();
+                assert_eq!(queue.front().unwrap().order_id, order_id("2"));
+                assert_eq!(ob.queue_ahead(order_id("1")), Some(qty(5)));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(17));
+            }
+
+            #[test]
+            fn price_change_requeues() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 5)).unwrap();
+
+                ob.replace_order_preserve_priority(buy_order("1".to_string(), 99, 4))
+                    .unwrap();
+
+                let queue = ob.orders_at(Side::Buy, price(99)).unwrap();
+                assert_eq!(queue.front().unwrap().order_id, order_id("2"));
+                assert!(ob.orders_at(Side::Buy, price(100)).is_none());
+            }
+
+            #[test]
+            fn partially_filled_upsize_requeues() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("a".to_string(), 100, 100)).unwrap();
+                ob.add_order(buy_order("b".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("s".to_string(), 100, 60)).unwrap();
+                ob.match_orders();
+
+                ob.replace_order_preserve_priority(buy_order("a".to_string(), 100, 50))
+                    .unwrap();
+
+                let queue = ob.orders_at(Side::Buy, price(100)).unwrap();
+                assert_eq!(queue.front().unwrap().order_id, order_id("b"));
+                assert_eq!(
+                    ob.get_order(order_id("a")).unwrap().remaining_quantity,
+                    qty(50)
+                );
+                assert_eq!(ob.queue_ahead(order_id("a")), Some(qty(5)));
+            }
+
+            #[test]
+            fn rejected_replacement_keeps_original() {
+                let mut ob = Orderbook::new();
+                ob.set_max_order_quantity(Some(qty(10)));
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+
+                assert_eq!(
+                    ob.replace_order_preserve_priority(buy_order("1".to_string(), 100, 50)),
+                    Err(OrderError::OrderTooLarge)
+                );
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Open));
+                assert_eq!(ob.queue_ahead(order_id("2")), Some(qty(10)));
+            }
+
+            #[test]
+            fn unknown_order_is_rejected() {
+                let mut ob = Orderbook::new();
+                let result = ob.replace_order_preserve_priority(buy_order("1".to_string(), 100, 4));
+                assert!(matches!(result, Err(OrderError::OrderNotFound)));
+            }
+        }
+
+        mod add_and_match {
+            use super::*;
+
+            #[test]
+            fn crossing_limit_returns_its_fills() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 3)).unwrap();
+
+                let fills = ob
+                    .add_and_match(buy_order("3".to_string(), 101, 5))
+                    .unwrap();
+
+                assert_eq!(fills.len(), 2);
+                assert_eq!(fills[0].price(), price(100));
+                assert_eq!(fills[0].quantity(), qty(3));
+                assert_eq!(fills[1].price(), price(101));
+                assert_eq!(fills[1].quantity(), qty(2));
+                assert_eq!(ob.order_status(order_id("3")), Some(OrderStatus::Filled));
+            }
+
+            #[test]
+            fn fill_and_kill_remainder_does_not_rest() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                let order = Order::new(
+                    order_id("2"),
+                    OrderType::FillAndKill,
+                    Side::Buy,
+                    price(100),
+                    qty(5),
+                );
+
+                let fills = ob.add_and_match(order).unwrap();
+
+                assert_eq!(fills.len(), 1);
+                assert_eq!(fills[0].quantity(), qty(3));
+                assert!(ob.get_levels().bids().is_empty());
+            }
+
+            #[test]
+            fn rejected_order_returns_error() {
+                let mut ob = Orderbook::new();
+                let order = Order::new(
+                    order_id("1"),
+                    OrderType::FillOrKill,
+                    Side::Buy,
+                    price(100),
+                    qty(5),
+                );
+                assert!(ob.add_and_match(order).is_err());
+                assert!(ob.trades().is_empty());
+            }
+        }
+
+        mod notional_to_sweep {
+            use super::*;
+
+            #[test]
+            fn sums_every_opposing_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 2)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 105, 1)).unwrap();
+                ob.add_order(buy_order("5".to_string(), 90, 3)).unwrap();
+
+                assert_eq!(ob.notional_to_sweep(Side::Buy), 1000 + 707 + 105);
+                assert_eq!(ob.notional_to_sweep(Side::Sell), 270);
+            }
+
+            #[test]
+            fn empty_opposing_side_is_zero() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 90, 3)).unwrap();
+                assert_eq!(ob.notional_to_sweep(Side::Buy), 0);
+            }
+        }
+
+        mod snapshot_restore {
+            use super::*;
+
+            fn auto_order(ob: &mut Orderbook, side: Side, p: u64, q: u64) -> OrderId {
+                let id = ob.generate_order_id();
+                ob.add_order(Order::new(
+                    id.clone(),
+                    OrderType::GoodTillCancelled,
+                    side,
+                    price(p),
+                    qty(q),
+                ))
+                .unwrap();
+                id
+            }
+
+            #[test]
+            fn auto_ids_do_not_collide_across_restore() {
+                let mut ob = Orderbook::new();
+                let mut issued = vec![
+                    auto_order(&mut ob, Side::Buy, 100, 5),
+                    auto_order(&mut ob, Side::Sell, 100, 5),
+                    auto_order(&mut ob, Side::Buy, 99, 5),
+                ];
+                // The first two fill and leave the book, so only the counter remembers them
+                ob.match_orders();
+
+                let mut restored = Orderbook::restore(ob.snapshot());
+                issued.push(auto_order(&mut restored, Side::Buy, 98, 1));
+                issued.push(auto_order(&mut restored, Side::Sell, 105, 1));
+
+                let unique: HashSet<OrderId> = issued.iter().cloned().collect();
+                assert_eq!(unique.len(), issued.len());
+            }
+
+            #[test]
+            fn restore_keeps_levels_and_queue_order() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 4)).unwrap();
+
+                let restored = Orderbook::restore(ob.snapshot());
+
+                assert_eq!(restored.get_levels(), ob.get_levels());
+                assert_eq!(restored.queue_ahead(order_id("2")), Some(qty(5)));
+                assert_eq!(restored.snapshot(), ob.snapshot());
+                assert!(restored.events().is_empty());
+            }
+        }
+
+        mod fok_fill_price {
+            use super::*;
+
+            #[test]
+            fn averages_across_levels() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 2)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 3)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 110, 10)).unwrap();
+
+                // 2 @ 100 + 3 @ 102 + 5 @ 110 = 1056 over 10
+                assert_eq!(
+                    ob.fok_fill_price(Side::Buy, price(110), qty(10)),
+                    Some(price(105))
+                );
+                assert_eq!(
+                    ob.fok_fill_price(Side::Buy, price(100), qty(2)),
+                    Some(price(100))
+                );
+            }
+
+            #[test]
+            fn none_when_limit_cannot_fully_fill() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 2)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 3)).unwrap();
+
+                assert_eq!(ob.fok_fill_price(Side::Buy, price(101), qty(3)), None);
+                assert_eq!(ob.fok_fill_price(Side::Buy, price(102), qty(6)), None);
+                assert!(!ob.can_fully_fill(Side::Buy, price(101), qty(3)));
+            }
+
+            #[test]
+            fn sell_walks_bids() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 1)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 96, 3)).unwrap();
+
+                assert_eq!(
+                    ob.fok_fill_price(Side::Sell, price(90), qty(4)),
+                    Some(price(97))
+                );
+            }
+        }
+
+        mod kill_switch {
+            use super::*;
+
+            #[test]
+            fn engaged_switch_rejects_adds() {
+                let mut ob = Orderbook::new();
+                ob.enable_kill_switch();
+                let result = ob.add_order(buy_order("1".to_string(), 100, 5));
+                assert!(matches!(result, Err(OrderError::KillSwitchEngaged)));
+                assert_eq!(ob.order_count(), 0);
+
+                ob.disable_kill_switch();
+                assert!(!ob.is_kill_switch_engaged());
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+            }
+
+            #[test]
+            fn engaged_switch_stops_matching() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.enable_kill_switch();
+
+                ob.match_orders();
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_count(), 2);
+
+                ob.disable_kill_switch();
+                ob.match_orders();
+                assert_eq!(ob.trades().len(), 1);
+            }
+
+            #[test]
+            fn engaged_switch_still_allows_cancels() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.enable_kill_switch();
+
+                ob.cancel_order(order_id("1")).unwrap();
+                assert_eq!(ob.order_count(), 0);
+                assert_eq!(ob.order_status(order_id("1")), Some(OrderStatus::Cancelled));
+            }
+
+            #[test]
+            fn engaged_switch_blocks_uncross() {
+                let mut ob = Orderbook::new();
+                ob.set_phase(BookPhase::PreOpen);
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.enable_kill_switch();
+
+                assert_eq!(ob.auction_uncross(), None);
+                assert_eq!(ob.phase(), BookPhase::PreOpen);
+                assert!(ob.trades().is_empty());
+
+                ob.disable_kill_switch();
+                assert_eq!(ob.auction_uncross(), Some((price(100), qty(5))));
+                assert_eq!(ob.trades().len(), 1);
+            }
+        }
+
+        mod flat_levels {
+            use super::*;
+
+            #[test]
+            fn round_trips_through_flat_format() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 98, 2)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 4)).unwrap();
+                let levels = ob.get_levels();
+
+                let flat = levels.to_flat();
+                assert_eq!(
+                    flat,
+                    vec![
+                        (Side::Buy, price(99), qty(5)),
+                        (Side::Buy, price(98), qty(2)),
+                        (Side::Sell, price(101), qty(4)),
+                    ]
+                );
+                assert_eq!(OrderBookLevels::from_flat(&flat), levels);
+            }
+
+            #[test]
+            fn empty_book_is_empty_flat() {
+                let levels = Orderbook::new().get_levels();
+                assert!(levels.to_flat().is_empty());
+                assert_eq!(OrderBookLevels::from_flat(&[]), levels);
+            }
+        }
+
+        mod min_quote_spread {
+            use super::*;
+
+            #[test]
+            fn quote_at_minimum_is_accepted() {
+                let mut ob = Orderbook::new();
+                ob.set_min_quote_spread(Some(price(4)));
+                ob.quote(price(98), price(102), qty(5)).unwrap();
+                assert_eq!(ob.order_count(), 2);
+            }
+
+            #[test]
+            fn quote_below_minimum_is_rejected() {
+                let mut ob = Orderbook::new();
+                ob.set_min_quote_spread(Some(price(4)));
+                let result = ob.quote(price(99), price(102), qty(5));
+                assert!(matches!(result, Err(OrderError::SpreadTooTight)));
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn individual_adds_are_not_checked() {
+                let mut ob = Orderbook::new();
+                ob.set_min_quote_spread(Some(price(4)));
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                assert_eq!(ob.order_count(), 2);
+            }
+        }
+
+        mod modification_count {
+            use super::*;
+
+            #[test]
+            fn counts_quantity_and_price_amends() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                assert_eq!(ob.modification_count(order_id("1")), Some(0));
+
+                ob.modify_order(order_id("1"), qty(8)).unwrap();
+                ob.modify_order_price(order_id("1"), price(99)).unwrap();
+                ob.modify_order(order_id("1"), qty(6)).unwrap();
+
+                assert_eq!(ob.modification_count(order_id("1")), Some(3));
+                assert_eq!(ob.modification_count(order_id("missing")), None);
+            }
+
+            #[test]
+            fn rejects_amends_past_the_limit() {
+                let mut ob = Orderbook::new();
+                ob.set_max_modifications(Some(2));
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.modify_order(order_id("1"), qty(8)).unwrap();
+                ob.modify_order_price(order_id("1"), price(99)).unwrap();
+
+                let result = ob.modify_order(order_id("1"), qty(5));
+                assert!(matches!(result, Err(OrderError::TooManyModifications)));
+                let result = ob.modify_order_price(order_id("1"), price(98));
+                assert!(matches!(result, Err(OrderError::TooManyModifications)));
+
+                assert_eq!(ob.modification_count(order_id("1")), Some(2));
+                assert_eq!(ob.get_levels().bids()[0].price(), price(99));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(8));
+            }
+        }
+
+        mod market_fallback_to_last {
+            use super::*;
+
+            #[test]
+            fn market_buy_rests_at_last_trade_when_asks_empty() {
+                let mut ob = Orderbook::new();
+                ob.set_market_fallback_to_last(true);
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.last_trade_price(), Some(price(100)));
+
+                ob.add_order(market_buy("3".to_string(), 4)).unwrap();
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[0].price(), price(100));
+                assert_eq!(levels.bids()[0].quantity(), qty(4));
+                assert_eq!(ob.order_status(order_id("3")), Some(OrderStatus::Open));
+            }
+
+            #[test]
+            fn still_rejected_without_a_last_trade() {
+                let mut ob = Orderbook::new();
+                ob.set_market_fallback_to_last(true);
+                let result = ob.add_order(market_buy("1".to_string(), 4));
+                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+            }
+
+            #[test]
+            fn disabled_by_default() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+
+                let result = ob.add_order(market_buy("3".to_string(), 4));
+                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+            }
+        }
+
+        mod validate {
+            use super::*;
+
+            fn typed(id: &str, order_type: OrderType, side: Side, p: Price, q: u64) -> Order {
+                Order::new(order_id(id), order_type, side, p, qty(q))
+            }
+
+            // validate must leave the book alone and agree with what add_order then does
+            fn assert_mirrors_add(ob: &Orderbook, order: Order, expected: Result<(), OrderError>) {
+                let before = ob.snapshot();
+                assert_eq!(ob.validate(&order), expected);
+                assert_eq!(ob.snapshot(), before);
+                assert_eq!(ob.clone().add_order(order), expected);
+            }
+
+            fn seeded_book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("ask".to_string(), 101, 5)).unwrap();
+                ob
+            }
+
+            #[test]
+            fn accepts_a_valid_order() {
+                let ob = seeded_book();
+                assert_mirrors_add(&ob, buy_order("1".to_string(), 100, 5), Ok(()));
+            }
+
+            #[test]
+            fn duplicate_id() {
+                let ob = seeded_book();
+                let order = buy_order("ask".to_string(), 100, 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::IdExists));
+            }
+
+            #[test]
+            fn fill_and_kill_without_match() {
+                let ob = seeded_book();
+                let order = typed("1", OrderType::FillAndKill, Side::Buy, price(100), 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::CantMatch));
+            }
+
+            #[test]
+            fn fill_or_kill_without_depth() {
+                let ob = seeded_book();
+                let order = typed("1", OrderType::FillOrKill, Side::Buy, price(101), 6);
+                assert_mirrors_add(&ob, order, Err(OrderError::CantFullyFill));
+            }
+
+            #[test]
+            fn market_without_liquidity() {
+                let ob = seeded_book();
+                let order = typed("1", OrderType::Market, Side::Sell, Price::min(), 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::NoLiquidity));
+            }
+
+            #[test]
+            fn reserved_limit_price() {
+                let ob = seeded_book();
+                let order = typed(
+                    "1",
+                    OrderType::GoodTillCancelled,
+                    Side::Buy,
+                    Price::max(),
+                    5,
+                );
+                assert_mirrors_add(&ob, order, Err(OrderError::ReservedPrice));
+            }
+
+            #[test]
+            fn oversized_order() {
+                let mut ob = seeded_book();
+                ob.set_max_order_quantity(Some(qty(4)));
+                let order = buy_order("1".to_string(), 100, 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::OrderTooLarge));
+            }
+
+            #[test]
+            fn disconnected_participant() {
+                let mut ob = seeded_book();
+                ob.disconnect_participant(7);
+                let order = buy_order("1".to_string(), 100, 5).with_participant(7);
+                assert_mirrors_add(&ob, order, Err(OrderError::ParticipantDisconnected));
+            }
+
+            #[test]
+            fn closed_book() {
+                let mut ob = seeded_book();
+                ob.set_phase(BookPhase::Closed);
+                let order = buy_order("1".to_string(), 100, 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::MarketClosed));
+            }
+
+            #[test]
+            fn kill_switch() {
+                let mut ob = seeded_book();
+                ob.enable_kill_switch();
+                let order = buy_order("1".to_string(), 100, 5);
+                assert_mirrors_add(&ob, order, Err(OrderError::KillSwitchEngaged));
+            }
+        }
+
+        mod load_csv {
+            use super::*;
+
+            #[test]
+            fn loads_rows_into_levels() {
+                let csv = "id,type,side,price,quantity\n\
+                           1,GoodTillCancelled,Buy,99,5\n\
+                           2,GoodForDay,Buy,99,3\n\
+                           3,GoodTillCancelled,Sell,101,4\n\
+                           \n\
+                           4,GoodTillCancelled,Sell,102,2\n";
+                let mut ob = Orderbook::new();
+
+                assert_eq!(ob.load_csv(csv.as_bytes()).unwrap(), 4);
+
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[0].price(), price(99));
+                assert_eq!(levels.bids()[0].quantity(), qty(8));
+                assert_eq!(levels.asks()[0].price(), price(101));
+                assert_eq!(levels.asks()[1].price(), price(102));
+            }
+
+            #[test]
+            fn unknown_type_reports_line() {
+                let csv = "1,GoodTillCancelled,Buy,99,5\n2,Iceberg,Buy,99,3\n";
+                let err = Orderbook::new().load_csv(csv.as_bytes()).unwrap_err();
+                assert!(matches!(
+                    err,
+                    ImportError::UnknownOrderType { line: 2, ref order_type } if order_type == "Iceberg"
+                ));
+            }
+
+            #[test]
+            fn malformed_rows_report_line() {
+                let err = Orderbook::new()
+                    .load_csv("1,GoodTillCancelled,Buy,99\n".as_bytes())
+                    .unwrap_err();
+                assert!(matches!(err, ImportError::MalformedRow { line: 1 }));
+
+                let err = Orderbook::new()
+                    .load_csv("1,Market,Buy,99,5\n2,GoodTillCancelled,Buy,9x,5\n".as_bytes())
+                    .unwrap_err();
+                assert!(matches!(err, ImportError::InvalidNumber { line: 2 }));
+
+                let err = Orderbook::new()
+                    .load_csv("1,GoodTillCancelled,Long,99,5\n".as_bytes())
+                    .unwrap_err();
+                assert!(matches!(err, ImportError::UnknownSide { line: 1, .. }));
+            }
+
+            #[test]
+            fn rejected_orders_are_not_counted() {
+                let csv = "1,GoodTillCancelled,Buy,99,5\n1,GoodTillCancelled,Buy,98,5\n";
+                let mut ob = Orderbook::new();
+                assert_eq!(ob.load_csv(csv.as_bytes()).unwrap(), 1);
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod orders_of_type {
+            use super::*;
+
+            #[test]
+            fn filters_resting_orders_by_type() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(Order::new(
+                    order_id("2"),
+                    OrderType::GoodForDay,
+                    Side::Buy,
+                    price(98),
+                    qty(5),
+                ))
+                .unwrap();
+                ob.add_order(Order::new(
+                    order_id("3"),
+                    OrderType::GoodForDay,
+                    Side::Sell,
+                    price(102),
+                    qty(5),
+                ))
+                .unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 5)).unwrap();
+
+                let gfd: Vec<OrderId> = ob
+                    .orders_of_type(OrderType::GoodForDay)
+                    .iter()
+                    .map(|o| o.order_id.clone())
+                    .collect();
+                assert_eq!(gfd, vec![order_id("2"), order_id("3")]);
+
+                let gtc = ob.orders_of_type(OrderType::GoodTillCancelled);
+                assert_eq!(gtc.len(), 2);
+                assert!(ob.orders_of_type(OrderType::MarketToLimit).is_empty());
+            }
+        }
+
+        mod malformed_state {
+            use super::*;
+
+            #[test]
+            fn zero_remaining_front_is_cleared_without_a_trade() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3)).unwrap();
+                let mut stuck = buy_order("0".to_string(), 101, 1);
+                stuck.remaining_quantity = qty(0);
+                ob.bids
+                    .entry(Reverse(price(101)))
+                    .or_default()
+                    .push_back(stuck);
+
+                ob.match_orders();
+
+                assert_eq!(ob.trades().len(), 1);
+                assert_eq!(ob.trades().last().unwrap().quantity(), qty(3));
+                assert!(ob.orders_at(Side::Buy, price(101)).is_none());
+            }
+
+            #[test]
+            fn empty_level_does_not_spin() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+                ob.bids.insert(Reverse(price(101)), Orders::new());
+
+                ob.match_orders();
+
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod quantity_at {
+            use super::*;
+
+            #[test]
+            fn sums_orders_at_exact_price() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 7)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 4)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 2)).unwrap();
+
+                assert_eq!(ob.quantity_at(Side::Buy, price(100)), qty(12));
+                assert_eq!(ob.quantity_at(Side::Sell, price(101)), qty(2));
+                assert_eq!(ob.quantity_at(Side::Buy, price(98)), qty(0));
+                assert_eq!(ob.quantity_at(Side::Sell, price(100)), qty(0));
+            }
+        }
+
+        mod market_order_policy {
+            use super::*;
+
+            fn partially_fillable_book(policy: MarketOrderPolicy) -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.set_market_order_policy(policy);
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 2)).unwrap();
+                ob
+            }
+
+            #[test]
+            fn immediate_or_cancel_drops_remainder() {
+                let mut ob = partially_fillable_book(MarketOrderPolicy::ImmediateOrCancel);
+                ob.add_order(market_buy("m".to_string(), 8)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().total_volume(), qty(5));
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.order_status(order_id("m")), Some(OrderStatus::Cancelled));
+            }
+
+            #[test]
+            fn rest_as_limit_keeps_remainder_at_last_fill() {
+                let mut ob = partially_fillable_book(MarketOrderPolicy::RestAsLimitAtLast);
+                ob.add_order(market_buy("m".to_string(), 8)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(ob.trades().total_volume(), qty(5));
+                let levels = ob.get_levels();
+                assert_eq!(levels.bids()[0].price(), price(101));
+                assert_eq!(levels.bids()[0].quantity(), qty(3));
+            }
+
+            #[test]
+            fn rest_as_limit_under_auto_match() {
+                let mut ob = partially_fillable_book(MarketOrderPolicy::RestAsLimitAtLast);
+                ob.set_auto_match(true);
+                ob.add_order(market_buy("m".to_string(), 8)).unwrap();
+
+                assert_eq!(ob.get_levels().bids()[0].price(), price(101));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(3));
+            }
+
+            #[test]
+            fn reject_refuses_partial_fills() {
+                let mut ob = partially_fillable_book(MarketOrderPolicy::Reject);
+                let result = ob.add_order(market_buy("m".to_string(), 8));
+                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+                ob.match_orders();
+                assert!(ob.trades().is_empty());
+
+                ob.add_order(market_buy("m".to_string(), 5)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.trades().total_volume(), qty(5));
+            }
+        }
+
+        mod distinct_participants {
+            use super::*;
+
+            #[test]
+            fn counts_each_participant_once() {
+                let mut ob = Orderbook::new();
+                assert_eq!(ob.distinct_participants(), 0);
+
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_participant(1))
+                    .unwrap();
+                ob.add_order(buy_order("2".to_string(), 98, 5).with_participant(1))
+                    .unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 5).with_participant(2))
+                    .unwrap();
+                ob.add_order(sell_order("4".to_string(), 102, 5).with_participant(2))
+                    .unwrap();
+                ob.add_order(sell_order("5".to_string(), 103, 5)).unwrap();
+
+                assert_eq!(ob.distinct_participants(), 2);
+            }
+        }
+
+        mod trade_context {
+            use super::*;
+
+            #[test]
+            fn reports_the_resting_leg_after_partial_fill() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                let trade = ob.trades().last().unwrap().clone();
+                assert_eq!(ob.trade_context(&trade), (None, Some(qty(6))));
+
+                ob.add_order(buy_order("3".to_string(), 100, 6)).unwrap();
+                ob.match_orders();
+                assert_eq!(ob.trade_context(&trade), (None, None));
+            }
+        }
+
+        mod priority_time {
+            use super::*;
+
+            #[test]
+            fn earlier_priority_time_jumps_the_queue() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                *time.lock().unwrap() += Duration::seconds(10);
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+
+                let replayed = buy_order("0".to_string(), 100, 5)
+                    .with_priority_time(start - Duration::seconds(1));
+                ob.add_order(replayed).unwrap();
+                let between = buy_order("1b".to_string(), 100, 5)
+                    .with_priority_time(start + Duration::seconds(5));
+                ob.add_order(between).unwrap();
+
+                let queue: Vec<OrderId> = ob
+                    .orders_at(Side::Buy, price(100))
+                    .unwrap()
+                    .iter()
+                    .map(|o| o.order_id.clone())
+                    .collect();
+                assert_eq!(
+                    queue,
+                    vec![order_id("0"), order_id("1"), order_id("1b"), order_id("2")]
+                );
+
+                ob.add_order(sell_order("s".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+                assert_eq!(
+                    ob.trades().last().unwrap().bid_trade.order_id(),
+                    order_id("0")
+                );
+            }
+
+            #[test]
+            fn iceberg_refill_drops_replayed_priority() {
+                let (clock, time) = mock_clock();
+                let start = *time.lock().unwrap();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+
+                *time.lock().unwrap() += Duration::seconds(10);
+                ob.add_order(
+                    sell_order("ice".to_string(), 100, 10)
+                        .with_iceberg(qty(3), qty(3))
+                        .with_priority_time(start),
+                )
+                .unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 3)).unwrap();
+                ob.match_orders();
+
+                let asks = ob.l3_snapshot(Side::Sell);
+                assert_eq!(asks[0].order_id, order_id("2"));
+                assert_eq!(asks[1].order_id, order_id("ice"));
+            }
+        }
+
+        mod resting_notional {
+            use super::*;
+
+            #[test]
+            fn sums_each_side_separately() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 2)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 97, 10)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 101, 3)).unwrap();
+
+                assert_eq!(ob.resting_notional(Side::Buy), 693 + 970);
+                assert_eq!(ob.resting_notional(Side::Sell), 303);
+            }
+
+            #[test]
+            fn waiting_market_order_does_not_overflow() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 3)).unwrap();
+                ob.add_order(market_buy("3".to_string(), 2)).unwrap();
+
+                assert_eq!(ob.resting_notional(Side::Buy), 495);
+            }
+        }
+
+        mod cancel_returns_order {
+            use super::*;
+
+            #[test]
+            fn partially_filled_cancel_reports_remaining_and_filled() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                let cancelled = ob.cancel_order(order_id("1")).unwrap();
+
+                let fills: u64 = ob.fills_for(order_id("1")).iter().map(|f| f.2.0).sum();
+                assert_eq!(cancelled.order_id, order_id("1"));
+                assert_eq!(cancelled.remaining_quantity, qty(3));
+                assert_eq!(cancelled.filled_quantity(), qty(fills));
+                assert_eq!(cancelled.filled_quantity(), qty(7));
+                assert!(ob.get_levels().bids().is_empty());
+            }
+
+            #[test]
+            fn untouched_cancel_reports_full_size() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 5)).unwrap();
+
+                let cancelled = ob.cancel_order(order_id("1")).unwrap();
+                assert_eq!(cancelled.remaining_quantity, qty(5));
+                assert_eq!(cancelled.filled_quantity(), qty(0));
+            }
+        }
+
+        mod market_state {
+            use super::*;
+
+            #[test]
+            fn classifies_normal_locked_and_crossed() {
+                let mut ob = Orderbook::new();
+                assert_eq!(ob.market_state(), MarketState::Normal);
+
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Normal);
+
+                ob.add_order(buy_order("3".to_string(), 101, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Locked);
+
+                ob.add_order(buy_order("4".to_string(), 102, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Crossed);
+            }
+
+            #[test]
+            fn one_sided_book_is_normal() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Normal);
+            }
+
+            #[test]
+            fn inverted_priority_crosses_downward() {
+                let mut ob = Orderbook::new();
+                ob.set_price_priority(PricePriority::Inverted);
+                ob.add_order(buy_order("1".to_string(), 101, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 99, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Normal);
+
+                ob.add_order(buy_order("3".to_string(), 98, 5)).unwrap();
+                assert_eq!(ob.market_state(), MarketState::Crossed);
+            }
+        }
+
+        mod post_only {
+            use super::*;
+
+            fn book_with_ask() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("ask".to_string(), 101, 5)).unwrap();
+                ob
+            }
+
+            #[test]
+            fn crossing_post_only_is_rejected() {
+                let mut ob = book_with_ask();
+                let result = ob.add_order(buy_order("1".to_string(), 102, 5).with_post_only());
+                assert!(matches!(result, Err(OrderError::WouldCross)));
+            }
+
+            #[test]
+            fn locking_post_only_accepted_by_default() {
+                let mut ob = book_with_ask();
+                ob.add_order(buy_order("1".to_string(), 101, 5).with_post_only())
+                    .unwrap();
+                assert_eq!(ob.market_state(), MarketState::Locked);
+            }
+
+            #[test]
+            fn locking_post_only_rejected_when_forbidden() {
+                let mut ob = book_with_ask();
+                ob.set_post_only_policy(PostOnlyPolicy::RejectLock);
+                let result = ob.add_order(buy_order("1".to_string(), 101, 5).with_post_only());
+                assert!(matches!(result, Err(OrderError::WouldLock)));
+
+                ob.add_order(buy_order("2".to_string(), 100, 5).with_post_only())
+                    .unwrap();
+                assert_eq!(ob.market_state(), MarketState::Normal);
+            }
+
+            #[test]
+            fn reprice_through_the_touch_is_rejected() {
+                let mut ob = book_with_ask();
+                ob.set_auto_match(true);
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_post_only())
+                    .unwrap();
+
+                assert_eq!(
+                    ob.modify_order_price(order_id("1"), price(102)),
+                    Err(OrderError::WouldCross)
+                );
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.get_order(order_id("1")).unwrap().price, price(99));
+
+                ob.set_post_only_policy(PostOnlyPolicy::RejectLock);
+                assert_eq!(
+                    ob.modify_order_price(order_id("1"), price(101)),
+                    Err(OrderError::WouldLock)
+                );
+                ob.modify_order_price(order_id("1"), price(100)).unwrap();
+            }
+
+            #[test]
+            fn shift_checks_against_the_shifted_touch() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(
+                    buy_order("1".to_string(), 99, 5)
+                        .with_participant(7)
+                        .with_post_only(),
+                )
+                .unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5).with_participant(7))
+                    .unwrap();
+
+                // The participant's own ask moves out of the way
+                ob.shift_participant_orders(7, 2).unwrap();
+                assert_eq!(ob.get_order(order_id("1")).unwrap().price, price(101));
+
+                ob.add_order(sell_order("3".to_string(), 102, 5)).unwrap();
+                assert_eq!(
+                    ob.shift_participant_orders(7, 2),
+                    Err(OrderError::WouldCross)
+                );
+                assert_eq!(ob.get_order(order_id("1")).unwrap().price, price(101));
+                assert!(ob.trades().is_empty());
+            }
+        }
+
+        mod shift_participant_orders {
+            use super::*;
+
+            #[test]
+            fn shifts_two_sided_quotes_together() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(buy_order("3".to_string(), 98, 5).with_participant(8))
+                    .unwrap();
+
+                ob.shift_participant_orders(7, 5).unwrap();
+
+                assert_eq!(ob.quantity_at(Side::Buy, price(104)), qty(5));
+                assert_eq!(ob.quantity_at(Side::Sell, price(106)), qty(5));
+                assert_eq!(ob.quantity_at(Side::Buy, price(99)), qty(0));
+                assert_eq!(ob.quantity_at(Side::Buy, price(98)), qty(5));
+            }
+
+            #[test]
+            fn shifted_order_joins_back_of_new_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+
+                ob.shift_participant_orders(7, 1).unwrap();
+
+                assert_eq!(ob.queue_ahead(order_id("1")), Some(qty(5)));
+            }
+
+            #[test]
+            fn invalid_result_changes_nothing() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 3, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 5).with_participant(7))
+                    .unwrap();
+                let before = ob.get_levels();
+
+                let result = ob.shift_participant_orders(7, -3);
+
+                assert!(matches!(result, Err(OrderError::ReservedPrice)));
+                assert_eq!(ob.get_levels(), before);
+            }
+
+            #[test]
+            fn counts_against_max_modifications() {
+                let mut ob = Orderbook::new();
+                ob.set_max_modifications(Some(1));
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 105, 5).with_participant(7))
+                    .unwrap();
+                ob.modify_order_price(order_id("2"), price(104)).unwrap();
+                let before = ob.get_levels();
+
+                assert_eq!(
+                    ob.shift_participant_orders(7, 1),
+                    Err(OrderError::TooManyModifications)
+                );
+                assert_eq!(ob.get_levels(), before);
+                assert_eq!(ob.modification_count(order_id("1")), Some(0));
+            }
+
+            #[test]
+            fn out_of_band_shift_changes_nothing() {
+                let mut ob = Orderbook::new();
+                ob.set_price_band(Some(price(5)));
+                ob.set_reference_price(price(100));
+                ob.add_order(buy_order("1".to_string(), 99, 5).with_participant(7))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 103, 5).with_participant(7))
+                    .unwrap();
+                let before = ob.get_levels();
+
+                assert_eq!(
+                    ob.shift_participant_orders(7, 3),
+                    Err(OrderError::OutsidePriceBand)
+                );
+                assert_eq!(ob.get_levels(), before);
+            }
+
+            #[test]
+            fn pegs_are_left_to_follow_the_book() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 5)).unwrap();
+                ob.add_order(
+                    Order::new(
+                        order_id("p"),
+                        OrderType::PeggedPrimary { offset: 0 },
+                        Side::Buy,
+                        price(1),
+                        qty(3),
+                    )
+                    .with_participant(7),
+                )
+                .unwrap();
+                ob.add_order(buy_order("3".to_string(), 97, 5).with_participant(7))
+                    .unwrap();
+
+                ob.shift_participant_orders(7, -2).unwrap();
+
+                assert_eq!(ob.get_order(order_id("3")).unwrap().price, price(95));
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(98));
+                assert_eq!(ob.modification_count(order_id("p")), Some(0));
+            }
+        }
+
+        mod sweep {
+            use super::*;
+
+            #[test]
+            fn stops_at_worst_price() {
+                let mut ob = Orderbook::new();
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 101, 4)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 102, 5)).unwrap();
+
+                let trades = ob.sweep(Side::Buy, qty(10), price(101));
+
+                let fills: Vec<(Price, Quantity)> =
+                    trades.iter().map(|t| (t.price(), t.quantity())).collect();
+                assert_eq!(fills, vec![(price(100), qty(3)), (price(101), qty(4))]);
+                // The unfilled 3 are dropped rather than resting
+                assert!(ob.get_levels().bids().is_empty());
+                assert_eq!(ob.get_levels().asks()[0].price(), price(102));
+            }
+
+            #[test]
+            fn nothing_within_limit_returns_no_trades() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 3)).unwrap();
+
+                assert!(ob.sweep(Side::Sell, qty(3), price(101)).is_empty());
+                assert_eq!(ob.order_count(), 1);
+            }
+        }
+
+        mod level_ends {
+            use super::*;
+
+            #[test]
+            fn reports_front_and_back_of_each_level() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 99, 5)).unwrap();
+
+                assert_eq!(
+                    ob.level_ends(Side::Buy),
+                    vec![
+                        (price(100), order_id("1"), order_id("3")),
+                        (price(99), order_id("4"), order_id("4")),
+                    ]
+                );
+                assert!(ob.level_ends(Side::Sell).is_empty());
+            }
+
+            #[test]
+            fn front_moves_as_orders_fill() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 100, 5)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 100, 5)).unwrap();
+                ob.match_orders();
+
+                assert_eq!(
+                    ob.level_ends(Side::Buy),
+                    vec![(price(100), order_id("2"), order_id("3"))]
+                );
+            }
+        }
+
+        mod min_resting_time {
+            use super::*;
+
+            #[test]
+            fn early_cancel_rejected_later_cancel_succeeds() {
+                let (clock, time) = mock_clock();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.set_min_resting_time(Some(Duration::milliseconds(500)));
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+
+                *time.lock().unwrap() += Duration::milliseconds(499);
+                let result = ob.cancel_order(order_id("1"));
+                assert!(matches!(result, Err(OrderError::MinRestingTimeNotMet)));
+                assert_eq!(ob.order_count(), 1);
+
+                *time.lock().unwrap() += Duration::milliseconds(1);
+                ob.cancel_order(order_id("1")).unwrap();
+                assert_eq!(ob.order_count(), 0);
+            }
+
+            #[test]
+            fn book_driven_cancels_ignore_the_minimum() {
+                let (clock, _) = mock_clock();
+                let mut ob = Orderbook::new();
+                ob.set_clock(clock);
+                ob.set_min_resting_time(Some(Duration::seconds(60)));
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(Order::new(
+                    order_id("2"),
+                    OrderType::FillAndKill,
+                    Side::Buy,
+                    price(100),
+                    qty(5),
+                ))
+                .unwrap();
+
+                ob.match_orders();
+
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.order_count(), 0);
+            }
+        }
+
+        mod get_order {
+            use super::*;
+
+            #[test]
+            fn returns_a_clone_with_current_fills() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 10).with_participant(3))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+
+                let order = ob.get_order(order_id("1")).unwrap();
+                assert_eq!(order.order_type, OrderType::GoodTillCancelled);
+                assert_eq!(order.side, Side::Buy);
+                assert_eq!(order.price, price(100));
+                assert_eq!(order.initial_quantity, qty(10));
+                assert_eq!(order.remaining_quantity, qty(6));
+                assert_eq!(order.participant_id, Some(3));
+            }
+
+            #[test]
+            fn none_for_filled_cancelled_and_unknown() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 100, 4)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 4)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 90, 4)).unwrap();
+                ob.match_orders();
+                ob.cancel_order(order_id("3")).unwrap();
+
+                assert!(ob.get_order(order_id("1")).is_none());
+                assert!(ob.get_order(order_id("3")).is_none());
+                assert!(ob.get_order(order_id("missing")).is_none());
+            }
+        }
+
+        mod midprice_rounding {
+            use super::*;
+
+            fn mid(bid: u64, ask: u64, rounding: MidpriceRounding) -> Price {
+                let mut ob = Orderbook::new();
+                ob.set_midprice_rounding(rounding);
+                ob.add_order(buy_order("1".to_string(), bid, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), ask, 5)).unwrap();
+                ob.midprice().unwrap()
+            }
+
+            #[test]
+            fn down_truncates_the_half() {
+                assert_eq!(mid(100, 101, MidpriceRounding::default()), price(100));
+                assert_eq!(mid(100, 101, MidpriceRounding::Down), price(100));
+            }
+
+            #[test]
+            fn up_rounds_the_half_up() {
+                assert_eq!(mid(100, 101, MidpriceRounding::Up), price(101));
+            }
+
+            #[test]
+            fn nearest_rounds_the_half_to_even() {
+                assert_eq!(mid(100, 101, MidpriceRounding::Nearest), price(100));
+                assert_eq!(mid(101, 102, MidpriceRounding::Nearest), price(102));
+            }
+
+            #[test]
+            fn even_spreads_are_exact_in_every_mode() {
+                for rounding in [
+                    MidpriceRounding::Down,
+                    MidpriceRounding::Up,
+                    MidpriceRounding::Nearest,
+                ] {
+                    assert_eq!(mid(100, 104, rounding), price(102));
+                }
+            }
+        }
+
+        mod level_summary {
+            use super::*;
+
+            #[test]
+            fn lists_each_level_with_quantity_and_count() {
+                let mut ob = Orderbook::new();
+                ob.add_order(buy_order("1".to_string(), 99, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 2)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 98, 4)).unwrap();
+                ob.add_order(sell_order("4".to_string(), 102, 1)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 101, 3)).unwrap();
+                ob.add_order(sell_order("6".to_string(), 101, 6)).unwrap();
+                ob.add_order(sell_order("7".to_string(), 101, 1)).unwrap();
+
+                assert_eq!(
+                    ob.level_summary(),
+                    vec![
+                        (Side::Buy, price(99), qty(7), 2),
+                        (Side::Buy, price(98), qty(4), 1),
+                        (Side::Sell, price(101), qty(10), 3),
+                        (Side::Sell, price(102), qty(1), 1),
+                    ]
+                );
+                assert!(Orderbook::new().level_summary().is_empty());
+            }
+        }
+
+        mod pegged_mid {
+            use super::*;
+
+            fn pegged_buy(id: &str, offset: i64, q: u64) -> Order {
+                Order::new(
+                    order_id(id),
+                    OrderType::PeggedMid { offset },
+                    Side::Buy,
+                    price(1),
+                    qty(q),
+                )
             }
 
             #[test]
-            fn fok_accepted_when_can_fully_fill_excess_liquidity() {
+            fn tracks_the_mid_as_the_book_moves() {
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 100)).unwrap();
-                let result = ob.add_order(buy_fok("2", 100, 50));
-                assert!(result.is_ok());
-            }
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 5)).unwrap();
+                ob.add_order(pegged_buy("p", 0, 3)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(100));
 
-            #[test]
-            fn fok_accepted_across_multiple_price_levels() {
-                let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 20)).unwrap();
-                ob.add_order(sell_order("2".to_string(), 101, 20)).unwrap();
-                ob.add_order(sell_order("3".to_string(), 102, 20)).unwrap();
-                let result = ob.add_order(buy_fok("4", 102, 50));
-                assert!(result.is_ok());
+                ob.add_order(buy_order("3".to_string(), 100, 5)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(101));
+
+                ob.cancel_order(order_id("3")).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(100));
             }
 
             #[test]
-            fn fok_sell_rejected_when_insufficient_bids() {
+            fn offset_applies_to_the_mid() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 30)).unwrap();
-                let result = ob.add_order(sell_fok("2", 100, 50));
-                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+                ob.add_order(buy_order("1".to_string(), 90, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 110, 5)).unwrap();
+                ob.add_order(pegged_buy("p", -2, 3)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(98));
             }
 
             #[test]
-            fn fok_sell_accepted_when_sufficient_bids() {
+            fn one_sided_book_keeps_peg_dormant() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
-                let result = ob.add_order(sell_fok("3", 99, 75));
-                assert!(result.is_ok());
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(pegged_buy("p", 0, 3)).unwrap();
+                assert!(ob.get_order(order_id("p")).is_none());
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Open));
+                assert_eq!(ob.get_levels().bids().len(), 1);
+
+                ob.add_order(sell_order("2".to_string(), 104, 5)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(101));
+
+                ob.cancel_order(order_id("2")).unwrap();
+                assert!(ob.get_order(order_id("p")).is_none());
+                ob.cancel_order(order_id("p")).unwrap();
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Cancelled));
             }
 
             #[test]
-            fn can_fully_fill_checks_price_constraint() {
+            fn peg_trades_against_incoming_flow() {
+                // Under auto-match the incoming order executes before the peg re-prices
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(sell_order("2".to_string(), 110, 50)).unwrap();
-                let result = ob.add_order(buy_fok("3", 100, 75));
-                assert!(matches!(result, Err(OrderError::CantFullyFill)));
+                ob.set_auto_match(true);
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 5)).unwrap();
+                ob.add_order(pegged_buy("p", 0, 3)).unwrap();
+
+                ob.add_order(sell_order("3".to_string(), 100, 3)).unwrap();
+
+                assert_eq!(
+                    ob.trades().last().unwrap().bid_trade.order_id(),
+                    order_id("p")
+                );
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Filled));
             }
         }
 
-        mod market_order {
+        mod pegged_primary {
             use super::*;
 
-            fn buy_market(id: &str, q: u64) -> Order {
-                Order::new(order_id(id), OrderType::Market, Side::Buy, price(0), qty(q))
-            }
-
-            fn sell_market(id: &str, q: u64) -> Order {
+            fn pegged(id: &str, side: Side, offset: i64) -> Order {
                 Order::new(
                     order_id(id),
-                    OrderType::Market,
-                    Side::Sell,
-                    price(0),
-                    qty(q),
+                    OrderType::PeggedPrimary { offset },
+                    side,
+                    price(1),
+                    qty(3),
                 )
             }
 
             #[test]
-            fn market_buy_rejected_when_no_liquidity() {
+            fn pegged_buy_follows_the_best_bid() {
                 let mut ob = Orderbook::new();
-                let result = ob.add_order(buy_market("1", 50));
-                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 105, 5)).unwrap();
+                ob.add_order(pegged("p", Side::Buy, 1)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(99));
+
+                ob.add_order(buy_order("3".to_string(), 101, 5)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(102));
+
+                ob.cancel_order(order_id("3")).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(99));
             }
 
             #[test]
-            fn market_sell_rejected_when_no_liquidity() {
+            fn pegged_sell_steps_down_from_the_ask() {
                 let mut ob = Orderbook::new();
-                let result = ob.add_order(sell_market("1", 50));
-                assert!(matches!(result, Err(OrderError::NoLiquidity)));
+                ob.add_order(sell_order("1".to_string(), 105, 5)).unwrap();
+                ob.add_order(pegged("p", Side::Sell, 2)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(103));
             }
 
             #[test]
-            fn market_buy_accepted_when_asks_exist() {
+            fn empty_pegged_side_keeps_order_dormant() {
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-                let result = ob.add_order(buy_market("2", 50));
-                assert!(result.is_ok());
+                ob.add_order(sell_order("1".to_string(), 105, 5)).unwrap();
+                ob.add_order(pegged("p", Side::Buy, 0)).unwrap();
+                assert!(ob.get_order(order_id("p")).is_none());
+
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(100));
+                assert_eq!(ob.queue_ahead(order_id("p")), Some(qty(5)));
             }
+        }
+
+        mod session_pnl {
+            use super::*;
 
             #[test]
-            fn market_sell_accepted_when_bids_exist() {
+            fn aggregates_maker_and_taker_legs() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                let result = ob.add_order(sell_market("2", 50));
-                assert!(result.is_ok());
+                ob.set_auto_match(true);
+                ob.set_fee_schedule(FeeSchedule {
+                    maker_bps: 10,
+                    taker_bps: 30,
+                });
+                ob.add_order(sell_order("1".to_string(), 1000, 10).with_participant(7))
+                    .unwrap();
+                ob.add_order(buy_order("2".to_string(), 1000, 10).with_participant(8))
+                    .unwrap();
+                ob.add_order(sell_order("3".to_string(), 1000, 5).with_participant(8))
+                    .unwrap();
+                ob.add_order(buy_order("4".to_string(), 1000, 5).with_participant(7))
+                    .unwrap();
+
+                let pnl = ob.session_pnl(7);
+                assert_eq!(pnl.volume, qty(15));
+                assert_eq!(pnl.notional, 15_000);
+                assert_eq!(pnl.fees_paid, 10 + 15);
+                assert_eq!(pnl.net, 10_000 - 5_000 - 25);
+
+                let other = ob.session_pnl(8);
+                assert_eq!(other.fees_paid, 30 + 5);
+                assert_eq!(other.net, 5_000 - 10_000 - 35);
             }
 
             #[test]
-            fn market_buy_uses_max_price() {
-                let order = buy_market("1", 50);
-                assert_eq!(order.price, Price::max());
+            fn maker_rebate_adds_to_net() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.set_fee_schedule(FeeSchedule {
+                    maker_bps: -5,
+                    taker_bps: 20,
+                });
+                ob.add_order(buy_order("1".to_string(), 1000, 10).with_participant(1))
+                    .unwrap();
+                ob.add_order(sell_order("2".to_string(), 1000, 10)).unwrap();
+
+                let pnl = ob.session_pnl(1);
+                assert_eq!(pnl.fees_paid, -5);
+                assert_eq!(pnl.net, -10_000 + 5);
+                assert_eq!(ob.session_pnl(2).volume, qty(0));
             }
+        }
+
+        mod price_bands {
+            use super::*;
 
             #[test]
-            fn market_sell_uses_min_price() {
-                let order = sell_market("1", 50);
-                assert_eq!(order.price, Price::min());
+            fn rejects_limits_outside_the_band() {
+                let mut ob = Orderbook::new();
+                ob.set_price_band(Some(price(5)));
+                ob.set_reference_price(price(100));
+
+                assert_eq!(
+                    ob.add_order(buy_order("1".to_string(), 94, 1)),
+                    Err(OrderError::OutsidePriceBand)
+                );
+                assert!(ob.add_order(buy_order("2".to_string(), 95, 1)).is_ok());
+                assert!(ob.add_order(sell_order("3".to_string(), 105, 1)).is_ok());
             }
 
             #[test]
-            fn market_order_ignores_specified_price() {
-                let order = Order::new(
-                    order_id("1"),
-                    OrderType::Market,
-                    Side::Buy,
-                    price(100),
-                    qty(50),
-                );
-                assert_eq!(order.price, Price::max());
+            fn new_reference_cancels_resting_orders_out_of_band() {
+                let mut ob = Orderbook::new();
+                ob.set_price_band(Some(price(5)));
+                ob.set_reference_price(price(100));
+                ob.add_order(buy_order("1".to_string(), 96, 1)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 1)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 104, 1)).unwrap();
+
+                ob.set_reference_price(price(102));
+                assert_eq!(ob.order_count(), 3);
+                assert_eq!(ob.enforce_price_bands(), 1);
+
+                assert!(ob.get_order(order_id("1")).is_none());
+                assert_eq!(ob.order_count(), 2);
+                assert_eq!(ob.enforce_price_bands(), 0);
             }
-        }
 
-        mod prune_good_for_day {
-            use super::*;
+            #[test]
+            fn no_band_cancels_nothing() {
+                let mut ob = Orderbook::new();
+                ob.set_reference_price(price(100));
+                ob.add_order(buy_order("1".to_string(), 50, 1)).unwrap();
+                assert_eq!(ob.enforce_price_bands(), 0);
+            }
 
-            fn buy_gfd(id: &str, p: u64, q: u64) -> Order {
+            fn banded_book() -> Orderbook {
+                let mut ob = Orderbook::new();
+                ob.set_price_band(Some(price(5)));
+                ob.set_reference_price(price(100));
+                ob
+            }
+
+            fn peg(id: &str, offset: i64) -> Order {
                 Order::new(
                     order_id(id),
-                    OrderType::GoodForDay,
+                    OrderType::PeggedPrimary { offset },
                     Side::Buy,
-                    price(p),
-                    qty(q),
+                    price(1),
+                    qty(3),
                 )
             }
 
-            fn sell_gfd(id: &str, p: u64, q: u64) -> Order {
-                Order::new(
-                    order_id(id),
-                    OrderType::GoodForDay,
-                    Side::Sell,
-                    price(p),
-                    qty(q),
-                )
+            #[test]
+            fn reprice_out_of_band_is_rejected() {
+                let mut ob = banded_book();
+                ob.add_order(buy_order("1".to_string(), 99, 1)).unwrap();
+
+                assert_eq!(
+                    ob.modify_order_price(order_id("1"), price(94)),
+                    Err(OrderError::OutsidePriceBand)
+                );
+                assert_eq!(
+                    ob.improve_order(order_id("1"), 7),
+                    Err(OrderError::OutsidePriceBand)
+                );
+                assert_eq!(ob.get_order(order_id("1")).unwrap().price, price(99));
             }
 
             #[test]
-            fn prune_empty_book_no_panic() {
-                let mut ob = Orderbook::new();
-                ob.prune_good_for_day_orders();
-                assert!(ob.get_levels().bids.is_empty());
-                assert!(ob.get_levels().asks.is_empty());
+            fn peg_priced_out_of_band_is_rejected() {
+                let mut ob = banded_book();
+                ob.add_order(buy_order("1".to_string(), 99, 1)).unwrap();
+
+                assert_eq!(ob.add_order(peg("p", 7)), Err(OrderError::OutsidePriceBand));
+                assert_eq!(ob.order_status(order_id("p")), None);
             }
 
             #[test]
-            fn prune_removes_gfd_bids() {
-                let mut ob = Orderbook::new();
-                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
-                ob.prune_good_for_day_orders();
-                assert!(ob.get_levels().bids.is_empty());
+            fn peg_repriced_out_of_band_is_cancelled() {
+                let mut ob = banded_book();
+                ob.add_order(buy_order("1".to_string(), 99, 1)).unwrap();
+                ob.add_order(peg("p", 3)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(102));
+
+                ob.add_order(buy_order("2".to_string(), 103, 1)).unwrap();
+
+                assert!(ob.get_order(order_id("p")).is_none());
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Cancelled));
             }
 
             #[test]
-            fn prune_removes_gfd_asks() {
-                let mut ob = Orderbook::new();
-                ob.add_order(sell_gfd("1", 100, 50)).unwrap();
-                ob.add_order(sell_gfd("2", 101, 50)).unwrap();
-                ob.prune_good_for_day_orders();
-                assert!(ob.get_levels().asks.is_empty());
+            fn dormant_peg_is_checked_when_it_revives() {
+                let mut ob = banded_book();
+                ob.add_order(peg("p", 3)).unwrap();
+                assert_eq!(ob.enforce_price_bands(), 0);
+
+                ob.add_order(buy_order("1".to_string(), 104, 1)).unwrap();
+
+                assert!(ob.get_order(order_id("p")).is_none());
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Cancelled));
             }
+        }
+
+        mod effective_spread {
+            use super::*;
 
             #[test]
-            fn prune_leaves_gtc_orders() {
+            fn doubles_distance_from_mid_to_average_fill() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(sell_order("2".to_string(), 110, 50)).unwrap();
-                ob.prune_good_for_day_orders();
+                ob.add_order(buy_order("1".to_string(), 98, 10)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 102, 10)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 106, 10)).unwrap();
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 1);
-                assert_eq!(levels.asks.len(), 1);
+                // Mid 100; 20 lots average 104
+                assert_eq!(ob.effective_spread(qty(10)), Some(price(4)));
+                assert_eq!(ob.effective_spread(qty(20)), Some(price(8)));
             }
 
             #[test]
-            fn prune_mixed_gtc_and_gfd() {
+            fn none_without_liquidity_or_mid() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
-                ob.add_order(buy_order("3".to_string(), 98, 50)).unwrap();
-                ob.add_order(buy_gfd("4", 97, 50)).unwrap();
+                ob.add_order(sell_order("1".to_string(), 102, 10)).unwrap();
+                assert_eq!(ob.effective_spread(qty(5)), None);
 
-                ob.prune_good_for_day_orders();
+                ob.add_order(buy_order("2".to_string(), 98, 10)).unwrap();
+                assert_eq!(ob.effective_spread(qty(11)), None);
+                assert_eq!(ob.effective_spread(qty(0)), None);
+            }
+        }
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 2);
-                assert_eq!(levels.bids[0].price(), price(100));
-                assert_eq!(levels.bids[1].price(), price(98));
+        mod largest_orders {
+            use super::*;
+
+            fn ids(orders: Vec<&Order>) -> Vec<&str> {
+                orders.into_iter().map(|o| o.order_id.as_str()).collect()
             }
 
             #[test]
-            fn prune_same_price_level_mixed() {
+            fn returns_top_orders_by_remaining_quantity() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
-                ob.add_order(buy_gfd("2", 100, 20)).unwrap();
-                ob.add_order(buy_order("3".to_string(), 100, 30)).unwrap();
+                ob.add_order(buy_order("1".to_string(), 100, 5)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
+                ob.add_order(buy_order("3".to_string(), 98, 20)).unwrap();
+                ob.add_order(buy_order("4".to_string(), 100, 20)).unwrap();
+                ob.add_order(sell_order("5".to_string(), 105, 500)).unwrap();
+
+                assert_eq!(ids(ob.largest_orders(Side::Buy, 3)), ["2", "4", "3"]);
+                assert_eq!(ids(ob.largest_orders(Side::Buy, 10)), ["2", "4", "3", "1"]);
+                assert_eq!(ids(ob.largest_orders(Side::Sell, 2)), ["5"]);
+                assert!(ob.largest_orders(Side::Buy, 0).is_empty());
+            }
+        }
 
-                ob.prune_good_for_day_orders();
+        mod reduce_only_auto_match {
+            use super::*;
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 1);
-                assert_eq!(levels.bids[0].quantity(), qty(40));
+            fn long_three(ob: &mut Orderbook) {
+                ob.set_auto_match(true);
+                ob.add_order(sell_order("1".to_string(), 100, 3)).unwrap();
+                ob.add_order(buy_order("2".to_string(), 100, 3).with_participant(1))
+                    .unwrap();
+                assert_eq!(ob.position(1), 3);
             }
 
             #[test]
-            fn prune_leaves_fak_orders() {
+            fn aggressor_capped_at_position() {
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 100)).unwrap();
-                ob.add_order(buy_fak("2".to_string(), 100, 50)).unwrap();
-                ob.match_orders();
-
-                ob.add_order(buy_gfd("3", 90, 50)).unwrap();
-                ob.prune_good_for_day_orders();
+                long_three(&mut ob);
+                ob.add_order(buy_order("3".to_string(), 99, 20)).unwrap();
+                ob.add_order(
+                    sell_order("4".to_string(), 99, 10)
+                        .with_participant(1)
+                        .with_reduce_only(),
+                )
+                .unwrap();
 
-                let levels = ob.get_levels();
-                assert!(levels.bids.is_empty());
+                assert_eq!(ob.position(1), 0);
+                assert_eq!(ob.trades().last().unwrap().quantity(), qty(3));
+                assert_eq!(ob.order_status(order_id("4")), Some(OrderStatus::Filled));
+                assert!(ob.get_levels().asks().is_empty());
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(17));
             }
 
             #[test]
-            fn prune_leaves_fok_orders() {
+            fn aggressor_without_position_is_cancelled() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
-
-                ob.prune_good_for_day_orders();
+                ob.set_auto_match(true);
+                ob.add_order(buy_order("1".to_string(), 100, 10)).unwrap();
+                ob.add_order(
+                    sell_order("2".to_string(), 100, 5)
+                        .with_participant(1)
+                        .with_reduce_only(),
+                )
+                .unwrap();
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 1);
-                assert_eq!(levels.bids[0].price(), price(100));
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.order_status(order_id("2")), Some(OrderStatus::Cancelled));
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(10));
             }
 
             #[test]
-            fn prune_after_partial_fill() {
+            fn resting_maker_capped_at_position() {
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 30)).unwrap();
-                ob.add_order(buy_gfd("2", 100, 50)).unwrap();
-                ob.match_orders();
+                long_three(&mut ob);
+                ob.add_order(
+                    sell_order("3".to_string(), 101, 10)
+                        .with_participant(1)
+                        .with_reduce_only(),
+                )
+                .unwrap();
+                ob.add_order(buy_order("4".to_string(), 101, 10)).unwrap();
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids[0].quantity(), qty(20));
+                assert_eq!(ob.position(1), 0);
+                assert_eq!(ob.trades().last().unwrap().quantity(), qty(3));
+                assert!(ob.get_levels().asks().is_empty());
+                assert_eq!(ob.get_levels().bids()[0].quantity(), qty(7));
+            }
+        }
 
-                ob.prune_good_for_day_orders();
+        mod dormant_pegs {
+            use super::*;
 
-                assert!(ob.get_levels().bids.is_empty());
+            fn dormant_peg(ob: &mut Orderbook) {
+                ob.add_order(sell_order("1".to_string(), 102, 5)).unwrap();
+                ob.add_order(
+                    Order::new(
+                        order_id("p"),
+                        OrderType::PeggedMid { offset: 0 },
+                        Side::Buy,
+                        price(1),
+                        qty(3),
+                    )
+                    .with_participant(9),
+                )
+                .unwrap();
+                assert!(ob.get_order(order_id("p")).is_none());
             }
 
             #[test]
-            fn prune_cleans_up_empty_price_levels() {
+            fn disconnect_cancels_dormant_pegs() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
-                ob.add_order(buy_gfd("3", 98, 50)).unwrap();
+                dormant_peg(&mut ob);
 
-                ob.prune_good_for_day_orders();
+                assert_eq!(ob.disconnect_participant(9), 1);
+                assert_eq!(ob.order_status(order_id("p")), Some(OrderStatus::Cancelled));
 
-                let levels = ob.get_levels();
-                assert!(levels.bids.is_empty());
+                ob.add_order(buy_order("2".to_string(), 100, 5)).unwrap();
+                assert!(ob.get_order(order_id("p")).is_none());
+                assert_eq!(ob.order_count(), 2);
             }
 
             #[test]
-            fn prune_multiple_times_is_idempotent() {
+            fn snapshot_carries_dormant_pegs() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 99, 50)).unwrap();
+                dormant_peg(&mut ob);
 
-                ob.prune_good_for_day_orders();
-                ob.prune_good_for_day_orders();
-                ob.prune_good_for_day_orders();
+                let snapshot = ob.snapshot();
+                assert_eq!(snapshot.dormant_pegs.len(), 1);
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 1);
-                assert_eq!(levels.bids[0].price(), price(100));
+                let mut restored = Orderbook::restore(snapshot);
+                restored
+                    .add_order(buy_order("2".to_string(), 100, 5))
+                    .unwrap();
+                assert_eq!(restored.get_order(order_id("p")).unwrap().price, price(101));
             }
 
             #[test]
-            fn prune_does_not_affect_trades() {
+            fn csv_loads_pegs_with_offset_in_price_column() {
                 let mut ob = Orderbook::new();
-                ob.add_order(sell_order("1".to_string(), 100, 50)).unwrap();
-                ob.add_order(buy_gfd("2", 100, 50)).unwrap();
-                ob.match_orders();
+                let csv = "1,GoodTillCancelled,Sell,102,5\np,PeggedMid,Buy,-1,3\n";
+                assert_eq!(ob.load_csv(csv.as_bytes()).unwrap(), 2);
+                assert_eq!(ob.snapshot().dormant_pegs.len(), 1);
 
-                assert_eq!(ob.trades().len(), 1);
+                ob.add_order(buy_order("2".to_string(), 98, 5)).unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(99));
+            }
+        }
 
-                ob.prune_good_for_day_orders();
+        mod peg_matching {
+            use super::*;
 
-                assert_eq!(ob.trades().len(), 1);
+            #[test]
+            fn peg_repriced_through_the_touch_trades() {
+                let mut ob = Orderbook::new();
+                ob.set_auto_match(true);
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 2)).unwrap();
+                ob.add_order(sell_order("3".to_string(), 101, 5)).unwrap();
+                ob.add_order(Order::new(
+                    order_id("p"),
+                    OrderType::PeggedPrimary { offset: 1 },
+                    Side::Buy,
+                    price(1),
+                    qty(3),
+                ))
+                .unwrap();
+                assert_eq!(ob.get_order(order_id("p")).unwrap().price, price(99));
+
+                ob.add_order(buy_order("4".to_string(), 99, 5)).unwrap();
+
+                let trade = ob.trades().last().unwrap();
+                assert_eq!(trade.bid_trade.order_id(), order_id("p"));
+                assert_eq!(trade.ask_trade.order_id(), order_id("2"));
+                assert_eq!(trade.quantity(), qty(2));
+                assert_eq!(trade.aggressor, Side::Buy);
+
+                let peg = ob.get_order(order_id("p")).unwrap();
+                assert_eq!((peg.price, peg.remaining_quantity), (price(100), qty(1)));
+                assert_eq!(ob.market_state(), MarketState::Normal);
             }
 
             #[test]
-            fn prune_both_sides() {
+            fn peg_rests_locked_without_auto_match() {
                 let mut ob = Orderbook::new();
-                ob.add_order(buy_gfd("1", 100, 50)).unwrap();
-                ob.add_order(buy_order("2".to_string(), 99, 50)).unwrap();
-                ob.add_order(sell_gfd("3", 110, 50)).unwrap();
-                ob.add_order(sell_order("4".to_string(), 111, 50)).unwrap();
+                ob.add_order(buy_order("1".to_string(), 98, 5)).unwrap();
+                ob.add_order(sell_order("2".to_string(), 100, 2)).unwrap();
+                ob.add_order(Order::new(
+                    order_id("p"),
+                    OrderType::PeggedPrimary { offset: 1 },
+                    Side::Buy,
+                    price(1),
+                    qty(3),
+                ))
+                .unwrap();
+                ob.add_order(buy_order("3".to_string(), 99, 5)).unwrap();
+
+                assert!(ob.trades().is_empty());
+                assert_eq!(ob.market_state(), MarketState::Locked);
+            }
+        }
 
-                ob.prune_good_for_day_orders();
+        mod iceberg_modify {
+            use super::*;
 
-                let levels = ob.get_levels();
-                assert_eq!(levels.bids.len(), 1);
-                assert_eq!(levels.bids[0].price(), price(99));
-                assert_eq!(levels.asks.len(), 1);
-                assert_eq!(levels.asks[0].price(), price(111));
+            #[test]
+            fn resize_sets_total_leaves_and_keeps_display() {
+                let mut ob = Orderbook::new();
+                ob.add_order(
+                    sell_order("ice".to_string(), 100, 100).with_iceberg(qty(10), qty(10)),
+                )
+                .unwrap();
+                ob.modify_order(order_id("ice"), qty(50)).unwrap();
+
+                let ice = ob.get_order(order_id("ice")).unwrap();
+                assert_eq!(ice.remaining_quantity, qty(10));
+                assert_eq!(ice.leaves_quantity(), qty(50));
+                assert_eq!(ice.initial_quantity, qty(50));
+                assert_eq!(ice.filled_quantity(), qty(0));
+            }
+
+            #[test]
+            fn resize_after_fill_keeps_filled_quantity() {
+                let mut ob = Orderbook::new();
+                ob.add_order(
+                    sell_order("ice".to_string(), 100, 100).with_iceberg(qty(10), qty(10)),
+                )
+                .unwrap();
+                ob.add_order(buy_order("1".to_string(), 100, 4)).unwrap();
+                ob.match_orders();
+                ob.modify_order(order_id("ice"), qty(3)).unwrap();
+
+                let ice = ob.get_order(order_id("ice")).unwrap();
+                assert_eq!(ice.remaining_quantity, qty(3));
+                assert_eq!(ice.hidden_quantity(), qty(0));
+                assert_eq!(ice.filled_quantity(), qty(4));
+                assert_eq!(ice.initial_quantity, qty(7));
             }
         }
     }
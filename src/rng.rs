@@ -0,0 +1,63 @@
+// Small deterministic generator so simulations can be replayed from a seed
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift64(u64);
+
+impl XorShift64 {
+    pub(crate) fn new(seed: u64) -> XorShift64 {
+        // xorshift never leaves the all-zero state
+        XorShift64(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    pub(crate) fn between(&mut self, low: u64, high: u64) -> u64 {
+        if low >= high {
+            return low;
+        }
+        match (high - low).checked_add(1) {
+            Some(span) => low + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = XorShift64::new(42);
+        let mut b = XorShift64::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn zero_seed_still_advances() {
+        let mut rng = XorShift64::new(0);
+        assert_ne!(rng.next_u64(), 0);
+    }
+
+    #[test]
+    fn between_stays_in_range() {
+        let mut rng = XorShift64::new(7);
+        for _ in 0..100 {
+            let value = rng.between(3, 6);
+            assert!((3..=6).contains(&value));
+        }
+        assert_eq!(rng.between(5, 5), 5);
+    }
+}
@@ -1,48 +1,193 @@
-use crate::{OrderId, Price, Quantity};
+use crate::{OrderId, Price, Quantity, Side, Symbol};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TradeInfo {
     order_id: OrderId,
     price: Price,
     quantity: Quantity,
+    participant_id: Option<u64>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Trade {
     pub bid_trade: TradeInfo,
     pub ask_trade: TradeInfo,
+    pub sequence: u64,
+    pub timestamp: DateTime<Local>,
+    pub aggressor: Side,
 }
 
+// A public tape shows one print per trade rather than the two legs
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TradePrint {
+    pub symbol: Option<Symbol>,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub timestamp: DateTime<Local>,
+    pub sequence: u64,
+    pub aggressor: Side,
+}
+
+// Net is the signed cash flow: sales minus purchases, less fees paid
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct SessionPnl {
+    pub volume: Quantity,
+    pub notional: u64,
+    pub fees_paid: i64,
+    pub net: i64,
+}
+
+// The sequence counter survives clear() so pollers never see a number reused
 #[derive(Debug, Eq, PartialEq, Clone, Default)]
-pub struct Trades(Vec<Trade>);
+pub struct Trades {
+    trades: Vec<Trade>,
+    last_sequence: u64,
+}
 
 impl Trades {
     pub fn new() -> Trades {
-        Trades(Vec::new())
+        Trades::default()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.trades.is_empty()
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.trades.len()
+    }
+
+    pub fn push(&mut self, mut trade: Trade) {
+        self.last_sequence += 1;
+        trade.sequence = self.last_sequence;
+        self.trades.push(trade)
     }
 
-    pub fn push(&mut self, trade: Trade) {
-        self.0.push(trade)
+    pub fn since(&self, sequence: u64) -> impl Iterator<Item = &Trade> {
+        let start = self
+            .trades
+            .partition_point(|trade| trade.sequence <= sequence);
+        self.trades[start..].iter()
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Trade> {
-        self.0.iter()
+        self.trades.iter()
     }
 
     pub fn clear(&mut self) {
-        self.0.clear()
+        self.trades.clear()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.trades.shrink_to_fit()
     }
 
     pub fn last(&self) -> Option<&Trade> {
-        self.0.last()
+        self.trades.last()
+    }
+
+    pub fn total_volume(&self) -> Quantity {
+        Quantity(
+            self.iter()
+                .fold(0u64, |acc, trade| acc.saturating_add(trade.quantity().0)),
+        )
+    }
+
+    pub fn total_notional(&self) -> u64 {
+        self.iter().fold(0u64, |acc, trade| {
+            acc.saturating_add(trade.price().notional(trade.quantity()))
+        })
+    }
+
+    pub fn vwap(&self) -> Option<Price> {
+        let volume = self.total_volume();
+        if volume == Quantity(0) {
+            return None;
+        }
+        Some(Price::new(self.total_notional() / volume.0))
+    }
+
+    pub fn high(&self) -> Option<Price> {
+        self.iter().map(|trade| trade.price()).max()
+    }
+
+    pub fn low(&self) -> Option<Price> {
+        self.iter().map(|trade| trade.price()).min()
+    }
+
+    pub fn last_price(&self) -> Option<Price> {
+        self.last().map(|trade| trade.price())
+    }
+
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "sequence,timestamp,bid_id,ask_id,price,quantity")?;
+        for trade in self.iter() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                trade.sequence,
+                trade.timestamp.to_rfc3339(),
+                trade.bid_trade.order_id.as_str(),
+                trade.ask_trade.order_id.as_str(),
+                trade.price().0,
+                trade.quantity().0
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn realized_spread(&self, reference_mid: Price) -> Vec<i64> {
+        self.iter()
+            .map(|trade| (trade.price().0 as i64).saturating_sub(reference_mid.0 as i64))
+            .collect()
+    }
+
+    pub fn volume_by_price(&self) -> BTreeMap<Price, Quantity> {
+        let mut profile = BTreeMap::new();
+        for trade in self.iter() {
+            *profile.entry(trade.price()).or_insert(Quantity(0)) += trade.quantity();
+        }
+        profile
+    }
+}
+
+impl Trade {
+    pub fn price(&self) -> Price {
+        self.bid_trade.price
+    }
+
+    pub fn quantity(&self) -> Quantity {
+        self.bid_trade.quantity
+    }
+
+    pub fn to_print(&self) -> TradePrint {
+        TradePrint {
+            symbol: None,
+            price: self.price(),
+            quantity: self.quantity(),
+            timestamp: self.timestamp,
+            sequence: self.sequence,
+            aggressor: self.aggressor,
+        }
+    }
+
+    pub fn likely_aggressor(&self, mid_before: Price) -> Side {
+        // Trades printing exactly at the mid are ambiguous; treat them as buyer-initiated
+        if self.price() < mid_before {
+            Side::Sell
+        } else {
+            Side::Buy
+        }
+    }
+}
+
+impl TradePrint {
+    pub fn with_symbol(mut self, symbol: Symbol) -> TradePrint {
+        self.symbol = Some(symbol);
+        self
     }
 }
 
@@ -52,8 +197,13 @@ impl TradeInfo {
             order_id,
             price,
             quantity,
+            participant_id: None,
         }
     }
+    pub fn with_participant(mut self, participant_id: Option<u64>) -> TradeInfo {
+        self.participant_id = participant_id;
+        self
+    }
     pub fn price(&self) -> Price {
         self.price
     }
@@ -63,12 +213,16 @@ impl TradeInfo {
     pub fn order_id(&self) -> OrderId {
         self.order_id.clone()
     }
+    pub fn participant_id(&self) -> Option<u64> {
+        self.participant_id
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{OrderId, Price, Quantity};
+    use crate::{OrderId, Price, Quantity, Side};
+    use chrono::TimeZone;
 
     fn order_id(id: String) -> OrderId {
         OrderId::new(id)
@@ -88,12 +242,17 @@ mod tests {
                 order_id: order_id(bid_id),
                 price: price(p),
                 quantity: qty(q),
+                participant_id: None,
             },
             ask_trade: TradeInfo {
                 order_id: order_id(ask_id),
                 price: price(p),
                 quantity: qty(q),
+                participant_id: None,
             },
+            sequence: 0,
+            timestamp: Local.with_ymd_and_hms(2024, 1, 2, 9, 30, 0).unwrap(),
+            aggressor: Side::Buy,
         }
     }
 
@@ -131,6 +290,38 @@ mod tests {
         assert_eq!(collected.len(), 2);
     }
 
+    #[test]
+    fn push_assigns_increasing_sequence() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 50));
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 101, 30));
+        let sequences: Vec<u64> = trades.iter().map(|t| t.sequence).collect();
+        assert_eq!(sequences, vec![1, 2]);
+    }
+
+    #[test]
+    fn since_returns_only_newer_trades() {
+        let mut trades = Trades::new();
+        for i in 0..5 {
+            trades.push(sample_trade(i.to_string(), "x".to_string(), 100 + i, 1));
+        }
+
+        let newer: Vec<Price> = trades.since(3).map(|t| t.price()).collect();
+        assert_eq!(newer, vec![price(103), price(104)]);
+        assert_eq!(trades.since(0).count(), 5);
+        assert_eq!(trades.since(5).count(), 0);
+    }
+
+    #[test]
+    fn sequence_continues_after_clear() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 50));
+        trades.clear();
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 101, 30));
+        assert_eq!(trades.last().unwrap().sequence, 2);
+        assert_eq!(trades.since(1).count(), 1);
+    }
+
     #[test]
     fn clear_removes_all_trades() {
         let mut trades = Trades::new();
@@ -157,6 +348,110 @@ mod tests {
         assert!(trades.last().is_none());
     }
 
+    #[test]
+    fn analytics_on_empty_tape() {
+        let trades = Trades::new();
+        assert_eq!(trades.total_volume(), qty(0));
+        assert_eq!(trades.total_notional(), 0);
+        assert!(trades.vwap().is_none());
+        assert!(trades.high().is_none());
+        assert!(trades.low().is_none());
+        assert!(trades.last_price().is_none());
+    }
+
+    #[test]
+    fn analytics_over_tape() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 10));
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 110, 30));
+        trades.push(sample_trade("5".to_string(), "6".to_string(), 95, 10));
+
+        assert_eq!(trades.total_volume(), qty(50));
+        assert_eq!(trades.total_notional(), 1000 + 3300 + 950);
+        assert_eq!(trades.vwap(), Some(price(105)));
+        assert_eq!(trades.high(), Some(price(110)));
+        assert_eq!(trades.low(), Some(price(95)));
+        assert_eq!(trades.last_price(), Some(price(95)));
+    }
+
+    #[test]
+    fn volume_by_price_sums_each_level() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 10));
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 101, 5));
+        trades.push(sample_trade("5".to_string(), "6".to_string(), 100, 7));
+        trades.push(sample_trade("7".to_string(), "8".to_string(), 99, 3));
+        trades.push(sample_trade("9".to_string(), "10".to_string(), 101, 1));
+
+        let profile = trades.volume_by_price();
+        assert_eq!(profile.len(), 3);
+        assert_eq!(profile[&price(99)], qty(3));
+        assert_eq!(profile[&price(100)], qty(17));
+        assert_eq!(profile[&price(101)], qty(6));
+    }
+
+    #[test]
+    fn realized_spread_is_signed_against_reference() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 103, 10));
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 98, 5));
+        trades.push(sample_trade("5".to_string(), "6".to_string(), 100, 1));
+
+        assert_eq!(trades.realized_spread(price(100)), vec![3, -2, 0]);
+        assert!(Trades::new().realized_spread(price(100)).is_empty());
+    }
+
+    #[test]
+    fn uptick_from_mid_is_buyer_initiated() {
+        let trade = sample_trade("1".to_string(), "2".to_string(), 101, 10);
+        assert_eq!(trade.likely_aggressor(price(100)), Side::Buy);
+    }
+
+    #[test]
+    fn downtick_from_mid_is_seller_initiated() {
+        let trade = sample_trade("1".to_string(), "2".to_string(), 99, 10);
+        assert_eq!(trade.likely_aggressor(price(100)), Side::Sell);
+    }
+
+    #[test]
+    fn write_csv_emits_header_and_rows() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 50));
+        trades.push(sample_trade("3".to_string(), "4".to_string(), 101, 30));
+
+        let mut out = Vec::new();
+        trades.write_csv(&mut out).unwrap();
+
+        let stamp = Local
+            .with_ymd_and_hms(2024, 1, 2, 9, 30, 0)
+            .unwrap()
+            .to_rfc3339();
+        let expected = format!(
+            "sequence,timestamp,bid_id,ask_id,price,quantity\n\
+             1,{stamp},1,2,100,50\n\
+             2,{stamp},3,4,101,30\n"
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn print_flattens_both_legs() {
+        let mut trades = Trades::new();
+        trades.push(sample_trade("1".to_string(), "2".to_string(), 100, 50));
+        let trade = trades.last().unwrap();
+
+        let print = trade.to_print();
+        assert_eq!(print.symbol, None);
+        assert_eq!(print.price, price(100));
+        assert_eq!(print.quantity, qty(50));
+        assert_eq!(print.timestamp, trade.timestamp);
+        assert_eq!(print.sequence, 1);
+        assert_eq!(print.aggressor, Side::Buy);
+
+        let symbol = Symbol::new("AAA".to_string());
+        assert_eq!(print.with_symbol(symbol.clone()).symbol, Some(symbol));
+    }
+
     #[test]
     fn trade_info_fields_accessible() {
         let trade = sample_trade("1".to_string(), "2".to_string(), 100, 50);
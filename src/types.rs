@@ -1,4 +1,7 @@
-use std::ops::{Sub, SubAssign};
+use crate::ParsePriceError;
+use chrono::Duration;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum OrderType {
@@ -7,6 +10,10 @@ pub enum OrderType {
     FillOrKill,
     GoodForDay,
     Market,
+    MarketToLimit,
+    GoodForDuration(Duration),
+    PeggedMid { offset: i64 },
+    PeggedPrimary { offset: i64 },
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -15,15 +22,140 @@ pub enum Side {
     Sell,
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum OrderStatus {
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum TradePricePolicy {
+    #[default]
+    MakerPrice,
+    Midpoint,
+    AggressorPrice,
+    PairMidpoint,
+}
+
+// Only odd spreads leave a half to round; Nearest sends it to the even price so it carries no bias
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum MidpriceRounding {
+    #[default]
+    Down,
+    Up,
+    Nearest,
+}
+
+// A one-sided or empty book counts as Normal
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MarketState {
+    Normal,
+    Locked,
+    Crossed,
+}
+
+// Post-only orders that cross are always rejected; some venues also forbid locking the touch
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum PostOnlyPolicy {
+    #[default]
+    AllowLock,
+    RejectLock,
+}
+
+// Decides what happens to the part of a market order the book cannot fill
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum MarketOrderPolicy {
+    #[default]
+    ImmediateOrCancel,
+    RestAsLimitAtLast,
+    Reject,
+}
+
+// Rates are in basis points of notional; a negative maker rate pays a rebate
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct FeeSchedule {
+    pub maker_bps: i64,
+    pub taker_bps: i64,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum BookPhase {
+    PreOpen,
+    #[default]
+    Continuous,
+    Closed,
+}
+
+// Inverted suits yield-quoted markets where a lower number is the better price
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum PricePriority {
+    #[default]
+    Normal,
+    Inverted,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum LevelPriority {
+    #[default]
+    Fifo,
+    Lifo,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy, PartialOrd, Ord)]
 pub struct Price(pub u64);
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, PartialOrd, Ord)]
 pub struct Quantity(pub u64);
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub struct OrderId(String);
 
+#[derive(Debug, Eq, PartialEq, Clone, Hash, PartialOrd, Ord)]
+pub struct Symbol(String);
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ScaledDisplay {
+    raw: u64,
+    scale: u32,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+impl OrderType {
+    pub fn is_market(self) -> bool {
+        matches!(self, OrderType::Market | OrderType::MarketToLimit)
+    }
+
+    pub fn is_pegged(self) -> bool {
+        matches!(
+            self,
+            OrderType::PeggedMid { .. } | OrderType::PeggedPrimary { .. }
+        )
+    }
+}
+
+impl FeeSchedule {
+    pub fn fee(self, notional: u64, is_taker: bool) -> i64 {
+        let bps = if is_taker {
+            self.taker_bps
+        } else {
+            self.maker_bps
+        };
+        // Truncates toward zero so neither fees nor rebates are rounded up
+        let fee = i128::from(notional) * i128::from(bps) / 10_000;
+        fee.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+    }
+}
+
 impl Price {
     pub fn new(value: u64) -> Self {
         Price(value)
@@ -36,12 +168,151 @@ impl Price {
     pub fn min() -> Self {
         Price(0)
     }
+
+    pub fn notional(self, quantity: Quantity) -> u64 {
+        self.0.saturating_mul(quantity.0)
+    }
+
+    // Parses e.g. "100.25" into ticks of 10^-decimals, so Price(10025) at 2 decimals
+    pub fn from_decimal(value: &str, decimals: u32) -> Result<Self, ParsePriceError> {
+        let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+        if whole.is_empty() {
+            return Err(ParsePriceError::Empty);
+        }
+        if !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(ParsePriceError::InvalidDigit);
+        }
+        if fraction.len() > decimals as usize {
+            return Err(ParsePriceError::TooManyDecimals);
+        }
+
+        let scale = 10u64
+            .checked_pow(decimals)
+            .ok_or(ParsePriceError::Overflow)?;
+        let whole: u64 = whole.parse().map_err(|_| ParsePriceError::Overflow)?;
+        let fraction: u64 = if fraction.is_empty() {
+            0
+        } else {
+            let padding = 10u64.pow(decimals - fraction.len() as u32);
+            fraction
+                .parse::<u64>()
+                .map_err(|_| ParsePriceError::Overflow)?
+                * padding
+        };
+
+        whole
+            .checked_mul(scale)
+            .and_then(|ticks| ticks.checked_add(fraction))
+            .map(Price)
+            .ok_or(ParsePriceError::Overflow)
+    }
+
+    pub fn display_scaled(self, decimals: u32) -> ScaledDisplay {
+        ScaledDisplay {
+            raw: self.0,
+            scale: decimals,
+        }
+    }
+}
+
+impl Quantity {
+    // Number of fractional digits in the raw value; 0 keeps whole units
+    pub const DEFAULT_SCALE: u32 = 0;
+
+    pub fn from_raw(raw: u64) -> Self {
+        Quantity(raw)
+    }
+
+    pub fn raw(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_units(units: f64) -> Self {
+        Quantity::from_units_scaled(units, Quantity::DEFAULT_SCALE)
+    }
+
+    pub fn from_units_scaled(units: f64, scale: u32) -> Self {
+        // Negative or NaN inputs saturate to zero
+        Quantity((units * 10f64.powi(scale as i32)).round() as u64)
+    }
+
+    pub fn to_units_scaled(self, scale: u32) -> f64 {
+        self.0 as f64 / 10f64.powi(scale as i32)
+    }
+
+    pub fn display_scaled(self, scale: u32) -> ScaledDisplay {
+        ScaledDisplay { raw: self.0, scale }
+    }
 }
 
 impl OrderId {
     pub fn new(value: String) -> Self {
         OrderId(value)
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Symbol {
+    pub fn new(value: String) -> Self {
+        Symbol(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ScaledDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.raw);
+        }
+        // From 20 decimals up the divisor no longer fits in a u64, and every raw value is
+        // below it, so the whole part is zero
+        let (whole, fraction) = match 10u64.checked_pow(self.scale) {
+            Some(divisor) => (self.raw / divisor, self.raw % divisor),
+            None => (0, self.raw),
+        };
+        write!(
+            f,
+            "{}.{:0width$}",
+            whole,
+            fraction,
+            width = self.scale as usize
+        )
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display_scaled(0).fmt(f)
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display_scaled(Quantity::DEFAULT_SCALE).fmt(f)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output {
+        Quantity(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 = self.0.saturating_add(rhs.0)
+    }
 }
 
 impl Sub for Quantity {
@@ -56,3 +327,138 @@ impl SubAssign for Quantity {
         self.0 = self.0.saturating_sub(rhs.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod price_tests {
+        use super::*;
+
+        #[test]
+        fn parses_fractional_price() {
+            let p = Price::from_decimal("100.25", 2).unwrap();
+            assert_eq!(p, Price(10025));
+            assert_eq!(p.display_scaled(2).to_string(), "100.25");
+        }
+
+        #[test]
+        fn pads_short_fractions_and_whole_numbers() {
+            assert_eq!(Price::from_decimal("100.5", 2), Ok(Price(10050)));
+            assert_eq!(Price::from_decimal("100", 2), Ok(Price(10000)));
+            assert_eq!(Price::from_decimal("7", 0), Ok(Price(7)));
+        }
+
+        #[test]
+        fn rejects_malformed_prices() {
+            assert_eq!(Price::from_decimal("", 2), Err(ParsePriceError::Empty));
+            assert_eq!(Price::from_decimal(".5", 2), Err(ParsePriceError::Empty));
+            assert_eq!(
+                Price::from_decimal("1.2.3", 2),
+                Err(ParsePriceError::InvalidDigit)
+            );
+            assert_eq!(
+                Price::from_decimal("-1", 2),
+                Err(ParsePriceError::InvalidDigit)
+            );
+            assert_eq!(
+                Price::from_decimal("1.234", 2),
+                Err(ParsePriceError::TooManyDecimals)
+            );
+            assert_eq!(
+                Price::from_decimal("18446744073709551615", 2),
+                Err(ParsePriceError::Overflow)
+            );
+        }
+
+        #[test]
+        fn fractional_prices_order_correctly() {
+            let a = Price::from_decimal("100.25", 2).unwrap();
+            let b = Price::from_decimal("100.3", 2).unwrap();
+            let c = Price::from_decimal("99.99", 2).unwrap();
+            assert!(c < a && a < b);
+            assert!(Price::min() < c && b < Price::max());
+        }
+
+        #[test]
+        fn display_defaults_to_raw_ticks() {
+            assert_eq!(Price(10025).to_string(), "10025");
+            assert_eq!(Price(5).display_scaled(2).to_string(), "0.05");
+        }
+
+        #[test]
+        fn display_handles_scales_beyond_u64() {
+            assert_eq!(
+                Price(u64::MAX).display_scaled(19).to_string(),
+                "1.8446744073709551615"
+            );
+            assert_eq!(
+                Price(5).display_scaled(20).to_string(),
+                "0.00000000000000000005"
+            );
+            assert_eq!(
+                Quantity(u64::MAX).display_scaled(25).to_string(),
+                "0.0000018446744073709551615"
+            );
+        }
+    }
+
+    mod quantity_tests {
+        use super::*;
+
+        #[test]
+        fn default_scale_is_whole_units() {
+            assert_eq!(Quantity::from_units(42.0), Quantity(42));
+            assert_eq!(Quantity(42).to_string(), "42");
+        }
+
+        #[test]
+        fn from_raw_round_trips() {
+            assert_eq!(Quantity::from_raw(150_000_000).raw(), 150_000_000);
+        }
+
+        #[test]
+        fn fractional_units_at_eight_decimals() {
+            let q = Quantity::from_units_scaled(1.5, 8);
+            assert_eq!(q.raw(), 150_000_000);
+            assert_eq!(q.display_scaled(8).to_string(), "1.50000000");
+            assert_eq!(q.to_units_scaled(8), 1.5);
+        }
+
+        #[test]
+        fn fractional_add_and_sub() {
+            let a = Quantity::from_units_scaled(1.25, 8);
+            let b = Quantity::from_units_scaled(0.5, 8);
+            assert_eq!((a + b).display_scaled(8).to_string(), "1.75000000");
+            assert_eq!((a - b).display_scaled(8).to_string(), "0.75000000");
+
+            let mut c = a;
+            c += b;
+            c -= Quantity::from_units_scaled(0.00000001, 8);
+            assert_eq!(c.raw(), 174_999_999);
+        }
+
+        #[test]
+        fn rounds_to_nearest_raw_unit() {
+            assert_eq!(
+                Quantity::from_units_scaled(0.123456789, 8).raw(),
+                12_345_679
+            );
+            assert_eq!(
+                Quantity::from_units_scaled(0.123456784, 8).raw(),
+                12_345_678
+            );
+            assert_eq!(Quantity::from_units(2.5), Quantity(3));
+        }
+
+        #[test]
+        fn negative_units_saturate_to_zero() {
+            assert_eq!(Quantity::from_units(-1.0), Quantity(0));
+        }
+
+        #[test]
+        fn add_saturates() {
+            assert_eq!(Quantity(u64::MAX) + Quantity(1), Quantity(u64::MAX));
+        }
+    }
+}